@@ -0,0 +1,173 @@
+use budget::*;
+use expression::*;
+use espresso;
+
+/// A single form for every expression that's the same up to ordering, nesting, repeats and absorption. Two
+/// expressions with the same canonical form are always equivalent, though equivalent expressions can still differ
+pub fn canonical(expression: &Expression) -> Expression {
+    let mut canonical = canonical_expression(expression);
+    if canonical.operands.len() == 1 && !is_threshold(&canonical.operator) {
+        return match canonical.operands.remove(0) {
+            Operand::Expression(inner) => inner,
+            test => Expression { operator: Operator::Or, operands: vec!(test) }, // A lone test is an OR
+        };
+    }
+    canonical
+}
+
+fn canonical_expression(expression: &Expression) -> Expression {
+    let mut operands = Vec::with_capacity(expression.operands.len());
+    for operand in &expression.operands {
+        match *operand {
+            Operand::Expression(ref inner) => {
+                flatten_into(&mut operands, Operand::Expression(canonical_expression(inner)), &expression.operator)
+            },
+            ref test => operands.push(test.clone()),
+        }
+    }
+    operands.sort();
+    if let Operator::AtLeast(_) = expression.operator {
+        return Expression { operator: expression.operator.clone(), operands }; // Every repeat counts towards it
+    }
+    operands.dedup();
+
+    // `X | (X & Y)` is just `X`, and `X & (X | Y)` is just `X`
+    let absorbed: Vec<bool> = operands.iter()
+        .map(|operand| operands.iter().any(|other| other != operand && absorbs(other, operand)))
+        .collect();
+    let operands = operands.into_iter()
+        .zip(absorbed)
+        .filter(|&(_, absorbed)| !absorbed)
+        .map(|(operand, _)| operand)
+        .collect();
+
+    Expression { operator: expression.operator.clone(), operands }
+}
+
+/// `(A)` is just `A`, and `A | (B | C)` is `A | B | C`. Thresholds don't nest that way
+fn flatten_into(operands: &mut Vec<Operand>, operand: Operand, operator: &Operator) {
+    match operand {
+        Operand::Expression(inner) => if inner.operands.len() == 1 && !is_threshold(&inner.operator)
+            || inner.operator == *operator && !is_threshold(operator) {
+            for inner_operand in inner.operands {
+                flatten_into(operands, inner_operand, operator);
+            }
+        } else {
+            operands.push(Operand::Expression(inner));
+        },
+        test => operands.push(test),
+    }
+}
+
+fn is_threshold(operator: &Operator) -> bool {
+    match *operator {
+        Operator::AtLeast(_) => true,
+        Operator::Or | Operator::And => false,
+    }
+}
+
+/// Every part of `operand` is also a part of `other`, where the parts of an operand are what its operator joins
+fn absorbs(operand: &Operand, other: &Operand) -> bool {
+    match (operand, other) {
+        (Operand::Expression(inner), _) if is_threshold(&inner.operator) => false,
+        (_, Operand::Expression(other)) if is_threshold(&other.operator) => false,
+        (Operand::Expression(inner), Operand::Expression(other)) => inner.operator == other.operator
+            && inner.operands.iter().all(|part| other.operands.contains(part)),
+        (Operand::Expression(_), _) => false,
+        (test, Operand::Expression(other)) => other.operands.contains(test),
+        (_, _) => false,
+    }
+}
+
+/// Steps that `equivalent` may spend on multiplying out and comparing two expressions
+pub const EQUIVALENCE_STEPS: u64 = 10_000;
+
+/// Whether every set of results that satisfies `a` also satisfies `b`, giving up once the budget runs out
+pub fn implies(a: &Expression, b: &Expression, budget: &Budget) -> Result<bool, BudgetExceeded> {
+    espresso::implies(a, b, budget)
+}
+
+/// Whether `a` and `b` are satisfied by exactly the same results, however they're written. If comparing them by
+/// meaning takes more than `EQUIVALENCE_STEPS`, only their canonical forms are compared
+pub fn equivalent(a: &Expression, b: &Expression) -> bool {
+    let budget = Budget::start(&BudgetLimits { time: None, steps: Some(EQUIVALENCE_STEPS) });
+    canonical(a) == canonical(b) || (implies(a, b, &budget) == Ok(true) && implies(b, a, &budget) == Ok(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tests(ids: &[i32]) -> Vec<Operand> {
+        ids.iter().map(|&id| Operand::Test(id)).collect()
+    }
+
+    fn or(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::Or, operands }
+    }
+
+    fn and(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::And, operands }
+    }
+
+    #[test]
+    fn should_ignore_operand_order() {
+        assert_eq!(canonical(&or(tests(&[1, 0]))), canonical(&or(tests(&[0, 1]))));
+        assert_eq!(canonical(&and(vec!(Operand::Expression(or(tests(&[3, 2]))), Operand::Test(1)))),
+                   and(vec!(Operand::Test(1), Operand::Expression(or(tests(&[2, 3]))))));
+    }
+
+    #[test]
+    fn should_flatten_and_deduplicate() {
+        // A | (B | (C | A)) | (B)
+        let nested = or(vec!(
+            Operand::Test(0),
+            Operand::Expression(or(vec!(Operand::Test(1), Operand::Expression(or(tests(&[2, 0])))))),
+            Operand::Expression(and(tests(&[1]))),
+        ));
+        assert_eq!(canonical(&nested), or(tests(&[0, 1, 2])));
+        assert_eq!(canonical(&and(vec!(Operand::Expression(or(tests(&[4])))))), or(tests(&[4])));
+    }
+
+    #[test]
+    fn should_absorb() {
+        // A | (A & B) | (B & C & D) | (C & B)
+        let expression = or(vec!(
+            Operand::Test(0),
+            Operand::Expression(and(tests(&[0, 1]))),
+            Operand::Expression(and(tests(&[1, 2, 3]))),
+            Operand::Expression(and(tests(&[2, 1]))),
+        ));
+        assert_eq!(canonical(&expression), or(vec!(Operand::Test(0), Operand::Expression(and(tests(&[1, 2]))))));
+        // A & (A | B)
+        assert_eq!(canonical(&and(vec!(Operand::Test(0), Operand::Expression(or(tests(&[0, 1])))))), or(tests(&[0])));
+    }
+
+    #[test]
+    fn should_compare_by_meaning() {
+        // (A & B) | (A & C) and A & (B | C)
+        let distributed = or(vec!(Operand::Expression(and(tests(&[0, 1]))), Operand::Expression(and(tests(&[0, 2])))));
+        let factored = and(vec!(Operand::Test(0), Operand::Expression(or(tests(&[1, 2])))));
+        assert_ne!(canonical(&distributed), canonical(&factored));
+        assert!(equivalent(&distributed, &factored));
+
+        let budget = Budget::unlimited();
+        assert_eq!(implies(&and(tests(&[0, 1])), &or(tests(&[1, 2])), &budget), Ok(true));
+        assert_eq!(implies(&or(tests(&[1, 2])), &and(tests(&[0, 1])), &budget), Ok(false));
+        assert!(!equivalent(&or(tests(&[0, 1])), &and(tests(&[0, 1]))));
+    }
+
+    #[test]
+    fn should_only_compare_canonical_forms_when_multiplying_out_is_too_big() {
+        // (A0 | B0) & (A1 | B1) & ... multiplies out into 2^20 terms
+        let factored = and((0..20)
+            .map(|pair| Operand::Expression(or(tests(&[2 * pair, 2 * pair + 1]))))
+            .collect());
+        let mut reordered = factored.clone();
+        reordered.operands.reverse();
+        let budget = Budget::start(&BudgetLimits { time: None, steps: Some(1000) });
+        assert_eq!(implies(&factored, &reordered, &budget), Err(BudgetExceeded::Steps));
+        assert!(equivalent(&factored, &reordered));
+        assert!(!equivalent(&factored, &and(tests(&[0, 2]))));
+    }
+}
@@ -1,12 +1,155 @@
+use cdt406::outliers::DetectionRule;
+use cdt406::evidence::EvidenceThresholds;
+use cdt406::reducer::{ReducerKind, ReducerSettings};
+use cdt406::budget::BudgetLimits;
+use cdt406::check::Finding;
+use cdt406::test_results::ExecutionFilter;
+use std::ops::Range;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Arguments {
     pub input_filename: String,
     pub output_filename: String,
 }
 
+/// `check manifest-filename input-filename [report-filename]`
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckArguments {
+    pub manifest_filename: String,
+    pub input_filename: String,
+    pub report_filename: Option<String>,
+}
+
+/// `run config-filename history-filename [report-filename]`
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunArguments {
+    pub config_filename: String,
+    pub history_filename: String,
+    pub report_filename: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Infer(Arguments),
+    Check(CheckArguments),
+    Run(RunArguments),
+    Import(Arguments), // Converts the input CSV history into a history store at the output
+    Learn(Option<String>), // Learns from the history, if there is one, and then from standard input
+}
+
+/// `FROM..TO`, where either end can be left out. `TO` isn't included
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ExecutionRange {
+    pub from: usize,
+    pub to: Option<usize>,
+}
+
+impl ExecutionRange {
+    pub fn within(&self, execution_count: usize) -> Range<usize> {
+        let to = self.to.map_or(execution_count, |to| to.min(execution_count));
+        self.from.min(to)..to
+    }
+}
+
+impl FromStr for ExecutionRange {
+    type Err = ();
+
+    fn from_str(range: &str) -> Result<ExecutionRange, ()> {
+        let (from, to) = range.split_once("..").ok_or(())?;
+        Ok(ExecutionRange {
+            from: if from.is_empty() { 0 } else { from.parse().map_err(|_| ())? },
+            to: if to.is_empty() { None } else { Some(to.parse().map_err(|_| ())?) },
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub flaky_report: Option<String>,
+    pub exclude_flaky: bool,
+    pub flaky_threshold: f64,
+    pub exclude_executions: Vec<usize>,
+    pub exclude_execution_ids: Vec<String>,
+    pub detection_rules: Vec<DetectionRule>,
+    pub thresholds: EvidenceThresholds,
+    pub suggest_experiments: Option<String>,
+    pub experiments: usize,
+    pub max_hypotheses: usize,
+    pub alternatives: bool,
+    pub factor: bool,
+    pub negative: bool,
+    pub at_least: bool,
+    pub infix: bool,
+    pub groups: bool,
+    pub threads: usize,
+    pub reducer: ReducerKind,
+    pub reducer_settings: ReducerSettings,
+    pub budget: BudgetLimits,
+    pub constraints: Option<String>,
+    pub fail_on: Vec<Finding>,
+    pub plan: Option<String>,
+    pub executions: Option<ExecutionRange>,
+    pub cache: Option<String>,
+    pub metadata: Option<String>,
+    pub filter: ExecutionFilter,
+    pub partition_by: Option<String>,
+    pub comparison: Option<String>,
+    pub merged: bool,
+    pub drift: Option<String>,
+    pub window: usize,
+    pub step: Option<usize>, // Half the window unless given
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            flaky_report: None,
+            exclude_flaky: false,
+            flaky_threshold: 0.0,
+            exclude_executions: Vec::new(),
+            exclude_execution_ids: Vec::new(),
+            detection_rules: Vec::new(),
+            thresholds: EvidenceThresholds::default(),
+            suggest_experiments: None,
+            experiments: 3,
+            max_hypotheses: 10000,
+            alternatives: false,
+            factor: false,
+            negative: false,
+            at_least: false,
+            infix: false,
+            groups: false,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            reducer: ReducerKind::Auto,
+            reducer_settings: ReducerSettings::default(),
+            budget: BudgetLimits::default(),
+            constraints: None,
+            fail_on: Finding::all(),
+            plan: None,
+            executions: None,
+            cache: None,
+            metadata: None,
+            filter: ExecutionFilter::default(),
+            partition_by: None,
+            comparison: None,
+            merged: false,
+            drift: None,
+            window: 50,
+            step: None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ArgsParseException {
-    InvalidNumberOfArguments(usize)
+    InvalidNumberOfArguments(usize),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    ConflictingOptions(String, String),
 }
 
 pub fn parse_cli_args(args: Vec<String>) -> Result<Arguments, ArgsParseException> {
@@ -17,10 +160,151 @@ pub fn parse_cli_args(args: Vec<String>) -> Result<Arguments, ArgsParseException
     }
 }
 
+/// Inferring is the default. `check` compares the history with a declared manifest instead, `run` runs the tests and
+/// adds their results to the history, `import` turns a CSV history into a history store, and `learn` keeps learning
+/// from standard input
+pub fn parse_command(args: Vec<String>) -> Result<Command, ArgsParseException> {
+    let subcommand = args.get(1).map(String::as_str);
+    if subcommand == Some("import") {
+        return parse_cli_args(args.into_iter().skip(1).collect()).map(Command::Import);
+    }
+    if subcommand == Some("learn") {
+        return match args.len() {
+            2 | 3 => Ok(Command::Learn(args.get(2).cloned())),
+            count => Err(ArgsParseException::InvalidNumberOfArguments(count)),
+        };
+    }
+    if subcommand != Some("check") && subcommand != Some("run") {
+        return parse_cli_args(args).map(Command::Infer);
+    }
+    if args.len() != 4 && args.len() != 5 {
+        return Err(ArgsParseException::InvalidNumberOfArguments(args.len()));
+    }
+    Ok(if subcommand == Some("check") {
+        Command::Check(CheckArguments {
+            manifest_filename: args[2].to_owned(),
+            input_filename: args[3].to_owned(),
+            report_filename: args.get(4).cloned(),
+        })
+    } else {
+        Command::Run(RunArguments {
+            config_filename: args[2].to_owned(),
+            history_filename: args[3].to_owned(),
+            report_filename: args.get(4).cloned(),
+        })
+    })
+}
+
+/// Splits `--option` flags out of the raw arguments, leaving the positional arguments behind
+/// for `parse_cli_args`
+pub fn parse_options(args: Vec<String>) -> Result<(Vec<String>, Options), ArgsParseException> {
+    let mut positional = Vec::<String>::new();
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--flaky-report" => options.flaky_report = Some(option_value(&arg, args.next())?),
+            "--exclude-flaky" => options.exclude_flaky = true,
+            "--flaky-threshold" => options.flaky_threshold = parse_value(&arg, args.next())?,
+            "--executions" => options.executions = Some(parse_value(&arg, args.next())?),
+            "--cache" => options.cache = Some(option_value(&arg, args.next())?),
+            "--metadata" => options.metadata = Some(option_value(&arg, args.next())?),
+            "--where" => {
+                let value = option_value(&arg, args.next())?;
+                let (key, label) = value.split_once('=')
+                    .ok_or_else(|| ArgsParseException::InvalidValue(arg.clone(), value.clone()))?;
+                options.filter.labels.push((key.to_owned(), label.to_owned()));
+            },
+            "--since" => options.filter.since = Some(option_value(&arg, args.next())?),
+            "--until" => options.filter.until = Some(option_value(&arg, args.next())?),
+            "--partition-by" => options.partition_by = Some(option_value(&arg, args.next())?),
+            "--comparison" => options.comparison = Some(option_value(&arg, args.next())?),
+            "--merged" => options.merged = true,
+            "--drift" => options.drift = Some(option_value(&arg, args.next())?),
+            "--window" => options.window = parse_value(&arg, args.next())?,
+            "--step" => options.step = Some(parse_value(&arg, args.next())?),
+            "--exclude-execution" => options.exclude_executions.push(parse_value(&arg, args.next())?),
+            "--exclude-execution-id" => options.exclude_execution_ids.push(option_value(&arg, args.next())?),
+            "--exclude-failed-fraction" => options.detection_rules
+                .push(DetectionRule::FailedFraction(parse_value(&arg, args.next())?)),
+            "--exclude-deviation" => options.detection_rules
+                .push(DetectionRule::Deviation(parse_value(&arg, args.next())?)),
+            "--min-passing-executions" => options.thresholds.min_passing_executions = parse_value(&arg, args.next())?,
+            "--min-passing-contexts" => options.thresholds.min_passing_contexts = parse_value(&arg, args.next())?,
+            "--suggest-experiments" => options.suggest_experiments = Some(option_value(&arg, args.next())?),
+            "--plan" => options.plan = Some(option_value(&arg, args.next())?),
+            "--experiments" => options.experiments = parse_value(&arg, args.next())?,
+            "--max-hypotheses" => options.max_hypotheses = parse_value(&arg, args.next())?,
+            "--alternatives" => options.alternatives = true,
+            "--factor" => options.factor = true,
+            "--negative" => options.negative = true,
+            "--at-least" => options.at_least = true,
+            "--constraints" => options.constraints = Some(option_value(&arg, args.next())?),
+            "--infix" => options.infix = true,
+            "--fail-on" => {
+                let value = option_value(&arg, args.next())?;
+                options.fail_on = value.split(',')
+                    .filter(|name| !name.is_empty())
+                    .map(|name| name.parse())
+                    .collect::<Result<Vec<Finding>, ()>>()
+                    .map_err(|_| ArgsParseException::InvalidValue(arg.clone(), value.clone()))?;
+            },
+            "--groups" => options.groups = true,
+            "--threads" => options.threads = parse_value(&arg, args.next())?,
+            "--reducer" => options.reducer = parse_value(&arg, args.next())?,
+            "--exact-variables" => options.reducer_settings.exact_variables = parse_value(&arg, args.next())?,
+            "--espresso-iterations" => {
+                options.reducer_settings.espresso_iterations = parse_value(&arg, args.next())?
+            },
+            "--time-budget-ms" => {
+                options.budget.time = Some(Duration::from_millis(parse_value(&arg, args.next())?))
+            },
+            "--step-budget" => options.budget.steps = Some(parse_value(&arg, args.next())?),
+            _ => return Err(ArgsParseException::UnknownOption(arg)),
+        }
+    }
+
+    // Each environment is inferred on its own, which none of these know about
+    if options.partition_by.is_some() {
+        let unsupported = [
+            ("--plan", options.plan.is_some()),
+            ("--drift", options.drift.is_some()),
+            ("--infix", options.infix),
+            ("--groups", options.groups),
+            ("--suggest-experiments", options.suggest_experiments.is_some()),
+            ("--cache", options.cache.is_some()),
+        ];
+        if let Some(&(option, _)) = unsupported.iter().find(|&&(_, given)| given) {
+            return Err(ArgsParseException::ConflictingOptions("--partition-by".to_owned(), option.to_owned()));
+        }
+    }
+
+    Ok((positional, options))
+}
+
+fn option_value(option: &str, value: Option<String>) -> Result<String, ArgsParseException> {
+    value.ok_or_else(|| ArgsParseException::MissingValue(option.to_owned()))
+}
+
+fn parse_value<T: ::std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, ArgsParseException> {
+    let value = option_value(option, value)?;
+    value.parse().map_err(|_| ArgsParseException::InvalidValue(option.to_owned(), value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
     #[test]
     fn should_err_on_wrong_count_of_params() {
         let result = parse_cli_args(vec!["test-dependencies".to_owned()]);
@@ -35,4 +319,107 @@ mod tests {
             output_filename: "output.json".to_owned()
         }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_separate_options_from_positional_args() {
+        let (positional, options) = parse_options(strings(&[
+            "test-dependencies", "--exclude-flaky", "input.csv", "--flaky-report", "flaky.json", "output.json"
+        ])).unwrap();
+        assert_eq!(positional, strings(&["test-dependencies", "input.csv", "output.json"]));
+        assert_eq!(options, Options {
+            flaky_report: Some("flaky.json".to_owned()),
+            exclude_flaky: true,
+            ..Options::default()
+        });
+    }
+
+    #[test]
+    fn should_reject_options_that_partitioning_ignores() {
+        assert!(parse_options(strings(&["--partition-by", "os", "--merged"])).is_ok());
+        assert_eq!(parse_options(strings(&["--infix", "--partition-by", "os"])),
+                   Err(ArgsParseException::ConflictingOptions("--partition-by".to_owned(), "--infix".to_owned())));
+    }
+
+    #[test]
+    fn should_collect_repeated_execution_exclusions() {
+        let (_, options) = parse_options(strings(&[
+            "--exclude-execution", "3", "--exclude-execution", "5", "--exclude-execution-id", "run-9",
+            "--exclude-failed-fraction", "0.9"
+        ])).unwrap();
+        assert_eq!(options.exclude_executions, vec!(3, 5));
+        assert_eq!(options.exclude_execution_ids, vec!("run-9".to_owned()));
+        assert_eq!(options.detection_rules, vec!(DetectionRule::FailedFraction(0.9)));
+    }
+
+    #[test]
+    fn should_parse_execution_ranges() {
+        let (_, options) = parse_options(strings(&["--executions", "10.."])).unwrap();
+        assert_eq!(options.executions.map(|range| range.within(50)), Some(10..50));
+        assert_eq!("..20".parse::<ExecutionRange>().map(|range| range.within(15)), Ok(0..15));
+        assert_eq!("5..3".parse::<ExecutionRange>().map(|range| range.within(15)), Ok(3..3));
+        assert!(parse_options(strings(&["--executions", "10"])).is_err());
+    }
+
+    #[test]
+    fn should_parse_import_command() {
+        assert_eq!(parse_command(strings(&["test-dependencies", "import", "history.csv", "history.cdth"])),
+                   Ok(Command::Import(Arguments {
+                       input_filename: "history.csv".to_owned(),
+                       output_filename: "history.cdth".to_owned(),
+                   })));
+    }
+
+    #[test]
+    fn should_parse_learn_command() {
+        assert_eq!(parse_command(strings(&["test-dependencies", "learn"])), Ok(Command::Learn(None)));
+    }
+
+    #[test]
+    fn should_parse_execution_filters() {
+        let (_, options) = parse_options(strings(&["--where", "branch=main", "--since", "2026-09-01"])).unwrap();
+        assert_eq!(options.filter.labels, vec!(("branch".to_owned(), "main".to_owned())));
+        assert_eq!(options.filter.since, Some("2026-09-01".to_owned()));
+        assert!(parse_options(strings(&["--where", "main"])).is_err());
+    }
+
+    #[test]
+    fn should_parse_budgets() {
+        let (_, options) = parse_options(strings(&["--time-budget-ms", "250", "--step-budget", "1000"])).unwrap();
+        assert_eq!(options.budget, BudgetLimits { time: Some(Duration::from_millis(250)), steps: Some(1000) });
+    }
+
+    #[test]
+    fn should_parse_check_command() {
+        let result = parse_command(strings(&["test-dependencies", "check", "manifest.txt", "input.csv"]));
+        assert_eq!(result, Ok(Command::Check(CheckArguments {
+            manifest_filename: "manifest.txt".to_owned(),
+            input_filename: "input.csv".to_owned(),
+            report_filename: None,
+        })));
+        assert_eq!(parse_command(strings(&["test-dependencies", "check", "manifest.txt"])),
+                   Err(ArgsParseException::InvalidNumberOfArguments(3)));
+        assert!(matches!(parse_command(strings(&["test-dependencies", "input.csv", "output.json"])),
+                         Ok(Command::Infer(_))));
+        assert_eq!(parse_command(strings(&["test-dependencies", "run", "tests.json", "history.csv", "run.json"])),
+                   Ok(Command::Run(RunArguments {
+                       config_filename: "tests.json".to_owned(),
+                       history_filename: "history.csv".to_owned(),
+                       report_filename: Some("run.json".to_owned()),
+                   })));
+
+        let (_, options) = parse_options(strings(&["--fail-on", "contradicted,undeclared"])).unwrap();
+        assert_eq!(options.fail_on, vec!(Finding::Contradicted, Finding::Undeclared));
+        assert!(parse_options(strings(&["--fail-on", "everything"])).is_err());
+    }
+
+    #[test]
+    fn should_err_on_bad_options() {
+        assert_eq!(parse_options(strings(&["--nope"])), Err(ArgsParseException::UnknownOption("--nope".to_owned())));
+        assert_eq!(parse_options(strings(&["--flaky-report"])),
+                   Err(ArgsParseException::MissingValue("--flaky-report".to_owned())));
+        assert_eq!(parse_options(strings(&["--flaky-threshold", "high"])),
+                   Err(ArgsParseException::InvalidValue("--flaky-threshold".to_owned(), "high".to_owned())));
+        assert_eq!(parse_options(strings(&["--reducer", "magic"])),
+                   Err(ArgsParseException::InvalidValue("--reducer".to_owned(), "magic".to_owned())));
+    }
+}
@@ -0,0 +1,144 @@
+/// A fixed-length vector of bits, packed 64 to a word
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct BitVector {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitVector {
+    pub fn new(len: usize) -> BitVector {
+        BitVector { words: vec!(0; len.div_ceil(64)), len }
+    }
+
+    pub fn from_bools(bits: &[bool]) -> BitVector {
+        let mut vector = BitVector::new(bits.len());
+        for (i, &bit) in bits.iter().enumerate() {
+            if bit {
+                vector.set(i, true);
+            }
+        }
+        vector
+    }
+
+    pub fn set(&mut self, i: usize, bit: bool) {
+        if bit {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// How many bits there are, set or not
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn and(&self, other: &BitVector) -> BitVector {
+        BitVector {
+            words: self.words.iter().zip(other.words.iter()).map(|(a, b)| a & b).collect(),
+            len: self.len,
+        }
+    }
+
+    pub fn or(&self, other: &BitVector) -> BitVector {
+        BitVector {
+            words: self.words.iter().zip(other.words.iter()).map(|(a, b)| a | b).collect(),
+            len: self.len,
+        }
+    }
+
+    /// Bits set here, but not in `other`
+    pub fn and_not(&self, other: &BitVector) -> BitVector {
+        BitVector {
+            words: self.words.iter().zip(other.words.iter()).map(|(a, b)| a & !b).collect(),
+            len: self.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Some bit is set in both
+    pub fn intersects(&self, other: &BitVector) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(a, b)| a & b != 0)
+    }
+
+    /// Every bit set here is also set in `other`
+    pub fn is_subset(&self, other: &BitVector) -> bool {
+        self.words.iter().zip(other.words.iter()).all(|(a, b)| a & !b == 0)
+    }
+
+    /// Indexes of the set bits, in increasing order
+    pub fn ones(&self) -> Ones<'_> {
+        Ones { vector: self, word_index: 0, word: self.words.first().cloned().unwrap_or(0) }
+    }
+}
+
+pub struct Ones<'a> {
+    vector: &'a BitVector,
+    word_index: usize,
+    word: u64, // What's left of the current word
+}
+
+impl<'a> Iterator for Ones<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.word == 0 {
+            self.word_index += 1;
+            if self.word_index >= self.vector.words.len() {
+                return None;
+            }
+            self.word = self.vector.words[self.word_index];
+        }
+
+        let bit = self.word.trailing_zeros() as usize;
+        self.word &= self.word - 1;
+        Some(self.word_index * 64 + bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_set_and_get_bits_across_words() {
+        let mut vector = BitVector::new(130);
+        vector.set(0, true);
+        vector.set(64, true);
+        vector.set(129, true);
+        vector.set(64, false);
+        assert!(vector.get(129) && !vector.get(64));
+        assert_eq!(vector.count_ones(), 2);
+        assert_eq!(vector.ones().collect::<Vec<usize>>(), vec!(0, 129));
+    }
+
+    #[test]
+    fn should_combine_word_wise() {
+        let a = BitVector::from_bools(&[true, true, false, false]);
+        let b = BitVector::from_bools(&[true, false, true, false]);
+        assert_eq!(a.and(&b), BitVector::from_bools(&[true, false, false, false]));
+        assert_eq!(a.or(&b), BitVector::from_bools(&[true, true, true, false]));
+        assert_eq!(a.and_not(&b), BitVector::from_bools(&[false, true, false, false]));
+        assert!(a.and(&b).is_subset(&a));
+        assert!(!a.is_subset(&b));
+        assert!(a.intersects(&b) && !a.and_not(&b).intersects(&b));
+    }
+
+    #[test]
+    fn should_iterate_nothing_when_empty() {
+        assert_eq!(BitVector::new(0).ones().count(), 0);
+        assert_eq!(BitVector::new(100).ones().count(), 0);
+        assert!(BitVector::new(100).is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// How much work a single test may take. `None` is unlimited
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct BudgetLimits {
+    pub time: Option<Duration>,
+    pub steps: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BudgetExceeded {
+    Time,
+    Steps,
+    Variables, // More variables than the reducer can handle at all, however much budget is left
+}
+
+impl BudgetExceeded {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BudgetExceeded::Time => "time",
+            BudgetExceeded::Steps => "steps",
+            BudgetExceeded::Variables => "variables",
+        }
+    }
+}
+
+/// Steps between looking at the clock, which takes much longer than a step
+const CLOCK_INTERVAL: u64 = 256;
+
+/// The work left for one test, counted down by whatever is working on it
+#[derive(Debug)]
+pub struct Budget {
+    deadline: Option<Instant>,
+    max_steps: Option<u64>,
+    steps: Cell<u64>,
+}
+
+impl Budget {
+    pub fn start(limits: &BudgetLimits) -> Budget {
+        Budget {
+            deadline: limits.time.map(|time| Instant::now() + time),
+            max_steps: limits.steps,
+            steps: Cell::new(0),
+        }
+    }
+
+    pub fn unlimited() -> Budget {
+        Budget::start(&BudgetLimits::default())
+    }
+
+    /// How many more steps there's room for, if steps are limited
+    pub fn remaining_steps(&self) -> Option<u64> {
+        self.max_steps.map(|max_steps| max_steps.saturating_sub(self.steps.get()))
+    }
+
+    /// Records one more step of work, failing once either limit has passed
+    pub fn step(&self) -> Result<(), BudgetExceeded> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        if self.max_steps.is_some_and(|max_steps| steps > max_steps) {
+            return Err(BudgetExceeded::Steps);
+        }
+        if steps.is_multiple_of(CLOCK_INTERVAL) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(BudgetExceeded::Time);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_run_out_of_steps() {
+        let budget = Budget::start(&BudgetLimits { time: None, steps: Some(3) });
+        assert_eq!((0..3).map(|_| budget.step()).collect::<Vec<_>>(), vec!(Ok(()); 3));
+        assert_eq!(budget.step(), Err(BudgetExceeded::Steps));
+        assert_eq!(budget.remaining_steps(), Some(0));
+        assert_eq!(Budget::unlimited().remaining_steps(), None);
+    }
+
+    #[test]
+    fn should_run_out_of_time() {
+        let budget = Budget::start(&BudgetLimits { time: Some(Duration::from_millis(0)), steps: None });
+        assert_eq!((0..CLOCK_INTERVAL).map(|_| budget.step()).last(), Some(Err(BudgetExceeded::Time)));
+        assert!((0..10 * CLOCK_INTERVAL).all(|_| Budget::unlimited().step().is_ok()));
+    }
+}
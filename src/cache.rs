@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use budget::BudgetExceeded;
+use constraints::violations;
+use evidence::*;
+use execution_matrix::*;
+use expression::*;
+use inference::*;
+use reducer::{ReducerKind, ReducerSettings};
+use store::*;
+
+const MAGIC: &[u8; 4] = b"CDTC";
+const VERSION: u8 = 2;
+
+/// Models from earlier runs, with everything that inferring a test looks at. A test whose passing executions are
+/// unchanged keeps its digest, so it doesn't need to be inferred again
+#[derive(Debug, PartialEq, Default)]
+pub struct ModelCache {
+    fingerprint: String, // The options and names that every model was inferred with
+    entries: BTreeMap<usize, (u128, Vec<u8>)>, // By target, with the digest of the contexts that it passed in
+}
+
+impl ModelCache {
+    /// An empty cache if the file doesn't exist yet
+    pub fn read(path: &str) -> Result<ModelCache, String> {
+        if !Path::new(path).exists() {
+            return Ok(ModelCache::default());
+        }
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| e.to_string())?;
+
+        let mut reader = Reader::new(&bytes);
+        if reader.bytes(4)? != MAGIC || reader.u8()? != VERSION {
+            return Err("Not a model cache".to_owned());
+        }
+        let fingerprint = reader.string()?;
+        let mut entries = BTreeMap::new();
+        while !reader.is_done() {
+            let target_id = reader.u32()? as usize;
+            let digest = (reader.u64()? as u128) << 64 | reader.u64()? as u128;
+            let length = reader.u32()? as usize;
+            entries.insert(target_id, (digest, reader.bytes(length)?.to_vec()));
+        }
+        Ok(ModelCache { fingerprint, entries })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut bytes = Vec::from(&MAGIC[..]);
+        bytes.push(VERSION);
+        put_string(&mut bytes, &self.fingerprint);
+        for (&target_id, &(digest, ref model)) in &self.entries {
+            put_u32(&mut bytes, target_id as u32);
+            put_u64(&mut bytes, (digest >> 64) as u64);
+            put_u64(&mut bytes, digest as u64);
+            put_u32(&mut bytes, model.len() as u32);
+            bytes.extend(model);
+        }
+        File::create(path)
+            .and_then(|mut file| file.write_all(&bytes))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// 128-bit FNV-1a over the number of tests and the contexts that the target passed in. It's wide enough that two
+/// different histories of the same test won't share a digest
+pub fn contexts_digest(test_count: usize, contexts: &[Context]) -> u128 {
+    let mut bytes = Vec::new();
+    put_u64(&mut bytes, test_count as u64);
+    for context in contexts {
+        put_u64(&mut bytes, context.executions as u64);
+        for test in context.passing.ones() {
+            put_u32(&mut bytes, test as u32);
+        }
+        bytes.push(0xff);
+    }
+
+    bytes.iter().fold(0x6c62272e07bb014262b821756295c58d, |hash, &byte| {
+        (hash ^ byte as u128).wrapping_mul(0x0000000001000000000000000000013b)
+    })
+}
+
+/// `infer_all`, reusing every cached model whose target and contexts still match. `fingerprint` should describe every
+/// option (and every name) that changes what's inferred, since nothing is reused if it differs. The cache is left with
+/// only the models of this run, and the tests that had to be inferred are returned with the models
+pub fn infer_all_cached(matrix: &ExecutionMatrix, options: &InferenceOptions, threads: usize, fingerprint: &str,
+                        cache: &mut ModelCache) -> (Vec<TestModel>, Vec<usize>) {
+    let digests: Vec<u128> = (0..matrix.test_count)
+        .map(|target_id| contexts_digest(matrix.test_count, &matrix.distinct_contexts(target_id)))
+        .collect();
+    let same_options = cache.fingerprint == fingerprint;
+    let mut models: Vec<Option<TestModel>> = digests.iter()
+        .enumerate()
+        .map(|(target_id, digest)| cache.entries.get(&target_id)
+            .filter(|&&(cached, _)| same_options && cached == *digest)
+            .and_then(|(_, bytes)| decode_model(bytes).ok())
+            .map(|mut model| {
+                // Cheap to find again, and not worth storing
+                model.violations = violations(&options.constraints, target_id, &matrix.distinct_contexts(target_id));
+                model
+            }))
+        .collect();
+
+    let stale: Vec<usize> = (0..matrix.test_count).filter(|&target_id| models[target_id].is_none()).collect();
+    for (&target_id, model) in stale.iter().zip(infer_some(matrix, &stale, options, threads)) {
+        models[target_id] = Some(model);
+    }
+
+    let models: Vec<TestModel> = models.into_iter().map(Option::unwrap).collect();
+    cache.fingerprint = fingerprint.to_owned();
+    cache.entries = digests.into_iter()
+        .zip(models.iter())
+        .enumerate()
+        .map(|(target_id, (digest, model))| (target_id, (digest, encode_model(model))))
+        .collect();
+    (models, stale)
+}
+
+pub fn encode_model(model: &TestModel) -> Vec<u8> {
+    let mut bytes = vec!(match model.status {
+        Status::Independent => 0,
+        Status::DependsOn => 1,
+        Status::InsufficientEvidence => 2,
+        Status::NeverPassed => 3,
+    });
+    put_u64(&mut bytes, model.evidence.passing_executions as u64);
+    put_u64(&mut bytes, model.evidence.passing_contexts as u64);
+    put_u64(&mut bytes, model.evidence.informative_contexts as u64);
+    bytes.push(model.evidence.independent as u8);
+
+    put_u32(&mut bytes, model.expression.iter().chain(model.alternatives.iter()).count() as u32);
+    for expression in model.expression.iter().chain(model.alternatives.iter()) {
+        put_expression(&mut bytes, expression);
+    }
+    bytes.push(model.gap.is_some() as u8);
+    put_u64(&mut bytes, model.gap.unwrap_or(0) as u64);
+    bytes.push(model.fallback.is_some() as u8);
+    if let Some(ref fallback) = model.fallback {
+        put_string(&mut bytes, fallback.reducer);
+        put_u32(&mut bytes, fallback.exceeded.len() as u32);
+        for &(reducer, reason) in &fallback.exceeded {
+            put_string(&mut bytes, reducer);
+            bytes.push(match reason {
+                BudgetExceeded::Time => 0,
+                BudgetExceeded::Steps => 1,
+                BudgetExceeded::Variables => 2,
+            });
+        }
+    }
+    bytes
+}
+
+/// What `encode_model` wrote, without any violations
+pub fn decode_model(bytes: &[u8]) -> Result<TestModel, String> {
+    let mut reader = Reader::new(bytes);
+    let status = match reader.u8()? {
+        0 => Status::Independent,
+        1 => Status::DependsOn,
+        2 => Status::InsufficientEvidence,
+        3 => Status::NeverPassed,
+        status => return Err(format!("Unknown status {}", status)),
+    };
+    let evidence = Evidence {
+        passing_executions: reader.u64()? as usize,
+        passing_contexts: reader.u64()? as usize,
+        informative_contexts: reader.u64()? as usize,
+        independent: reader.u8()? != 0,
+    };
+
+    let mut alternatives = Vec::new();
+    for _ in 0..reader.u32()? {
+        alternatives.push(read_expression(&mut reader)?);
+    }
+    let expression = if alternatives.is_empty() { None } else { Some(alternatives.remove(0)) };
+    let has_gap = reader.u8()? != 0;
+    let gap = reader.u64()? as usize;
+    let fallback = if reader.u8()? != 0 {
+        let reducer = reducer_name(&reader.string()?)?;
+        let mut exceeded = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reducer_name(&reader.string()?)?;
+            let reason = match reader.u8()? {
+                0 => BudgetExceeded::Time,
+                1 => BudgetExceeded::Steps,
+                _ => BudgetExceeded::Variables,
+            };
+            exceeded.push((name, reason));
+        }
+        Some(Fallback { exceeded, reducer })
+    } else {
+        None
+    };
+
+    Ok(TestModel {
+        status,
+        evidence,
+        expression,
+        alternatives,
+        gap: if has_gap { Some(gap) } else { None },
+        fallback,
+        violations: Vec::new(),
+    })
+}
+
+/// The reducers' own names, so they outlive the bytes they were read from
+fn reducer_name(name: &str) -> Result<&'static str, String> {
+    name.parse::<ReducerKind>()
+        .map(|kind| kind.build(&ReducerSettings::default()).name())
+        .map_err(|_| format!("Unknown reducer {}", name))
+}
+
+fn put_expression(bytes: &mut Vec<u8>, expression: &Expression) {
+    match expression.operator {
+        Operator::Or => bytes.push(0),
+        Operator::And => bytes.push(1),
+        Operator::AtLeast(threshold) => {
+            bytes.push(2);
+            put_u32(bytes, threshold as u32);
+        },
+    }
+    put_u32(bytes, expression.operands.len() as u32);
+    for operand in &expression.operands {
+        match *operand {
+            Operand::Test(id) => {
+                bytes.push(0);
+                put_u32(bytes, id as u32);
+            },
+            Operand::Not(id) => {
+                bytes.push(1);
+                put_u32(bytes, id as u32);
+            },
+            Operand::Expression(ref inner) => {
+                bytes.push(2);
+                put_expression(bytes, inner);
+            },
+        }
+    }
+}
+
+fn read_expression(reader: &mut Reader) -> Result<Expression, String> {
+    let operator = match reader.u8()? {
+        0 => Operator::Or,
+        1 => Operator::And,
+        2 => Operator::AtLeast(reader.u32()? as usize),
+        operator => return Err(format!("Unknown operator {}", operator)),
+    };
+    let mut operands = Vec::new();
+    for _ in 0..reader.u32()? {
+        operands.push(match reader.u8()? {
+            0 => Operand::Test(reader.u32()? as i32),
+            1 => Operand::Not(reader.u32()? as i32),
+            2 => Operand::Expression(read_expression(reader)?),
+            operand => return Err(format!("Unknown operand {}", operand)),
+        });
+    }
+    Ok(Expression { operator, operands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use budget::BudgetLimits;
+    use std::{env, fs, process};
+
+    #[test]
+    fn should_read_back_models() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, false, true],
+            &[true, false, true, false, true],
+            &[true, false, false, true, true],
+            &[true, true, true, true, false],
+        ];
+        let options = InferenceOptions {
+            alternatives: true,
+            budget: BudgetLimits { time: None, steps: Some(1) },
+            ..InferenceOptions::default()
+        };
+        for model in infer_all(&ExecutionMatrix::new(slice), &options, 1) {
+            assert_eq!(decode_model(&encode_model(&model)), Ok(model));
+        }
+        assert!(decode_model(&[7]).is_err());
+    }
+
+    #[test]
+    fn should_only_infer_tests_whose_passing_executions_changed() {
+        let before: &[&[bool]] = &[&[true, true, false], &[true, false, false], &[false, true, true]];
+        let after: &[&[bool]] = &[
+            &[true, true, false, true],
+            &[true, false, false, false],
+            &[false, true, true, false],
+        ];
+        let options = InferenceOptions::default();
+        let mut cache = ModelCache::default();
+
+        let (_, inferred) = infer_all_cached(&ExecutionMatrix::new(before), &options, 1, "", &mut cache);
+        assert_eq!(inferred, vec!(0, 1, 2));
+        let (models, inferred) = infer_all_cached(&ExecutionMatrix::new(after), &options, 1, "", &mut cache);
+        assert_eq!(inferred, vec!(0));
+        assert_eq!(models, infer_all(&ExecutionMatrix::new(after), &options, 1));
+        assert_eq!(cache.len(), 3);
+
+        let (_, inferred) = infer_all_cached(&ExecutionMatrix::new(after), &options, 1, "--factor", &mut cache);
+        assert_eq!(inferred, vec!(0, 1, 2));
+
+        let path = env::temp_dir().join(format!("cdt406-cache-{}", process::id()));
+        let path = path.to_str().unwrap();
+        cache.write(path).unwrap();
+        assert_eq!(ModelCache::read(path), Ok(cache));
+        fs::remove_file(path).unwrap();
+    }
+}
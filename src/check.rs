@@ -0,0 +1,213 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use constraints::*;
+use evidence::Status;
+use execution_matrix::ExecutionMatrix;
+use expression::*;
+use inference::TestModel;
+
+/// A test depending on another test
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Edge {
+    pub test: usize,
+    pub dependency: usize,
+}
+
+/// The ways that a declared dependency manifest can disagree with the history
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Finding {
+    Unobserved, // Declared, but not part of the inferred dependency
+    Undeclared, // Part of the inferred dependency, but not declared
+    Contradicted, // Declared, but the test passed while the dependency failed
+}
+
+impl Finding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Finding::Unobserved => "unobserved",
+            Finding::Undeclared => "undeclared",
+            Finding::Contradicted => "contradicted",
+        }
+    }
+
+    /// Each kind of finding sets its own bit of the exit code, so CI can tell them apart
+    pub fn exit_bit(&self) -> i32 {
+        match *self {
+            Finding::Unobserved => 1,
+            Finding::Undeclared => 2,
+            Finding::Contradicted => 4,
+        }
+    }
+
+    pub fn all() -> Vec<Finding> {
+        vec!(Finding::Unobserved, Finding::Undeclared, Finding::Contradicted)
+    }
+}
+
+impl FromStr for Finding {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Finding, ()> {
+        Finding::all().into_iter().find(|finding| finding.name() == name).ok_or(())
+    }
+}
+
+/// How the inferred dependencies compare with the declared ones
+#[derive(Debug, PartialEq, Default)]
+pub struct Check {
+    pub unobserved: Vec<Edge>,
+    pub undeclared: Vec<Edge>,
+    pub contradicted: Vec<(Edge, usize)>, // With how many executions the test passed in without the dependency
+}
+
+impl Check {
+    pub fn findings(&self, finding: Finding) -> usize {
+        match finding {
+            Finding::Unobserved => self.unobserved.len(),
+            Finding::Undeclared => self.undeclared.len(),
+            Finding::Contradicted => self.contradicted.len(),
+        }
+    }
+
+    /// Zero when none of the `fail_on` findings were made
+    pub fn exit_code(&self, fail_on: &[Finding]) -> i32 {
+        fail_on.iter()
+            .filter(|&&finding| self.findings(finding) > 0)
+            .fold(0, |code, finding| code | finding.exit_bit())
+    }
+}
+
+/// The edges a manifest declares. A manifest is a constraints file that only has required dependencies, such as
+/// `X -> Y` for "test X requires fixture Y"
+pub fn declared_edges(constraints: &[Constraint], lookup: &[String]) -> Result<Vec<Edge>, String> {
+    constraints.iter()
+        .map(|constraint| match *constraint {
+            Constraint::Required { test, dependency } => Ok(Edge { test, dependency }),
+            ref other => Err(format!("A manifest can only declare dependencies, found {}", other.infix(lookup))),
+        })
+        .collect()
+}
+
+/// The tests that each inferred dependency needs to pass. Tests that it needs to fail aren't dependencies
+pub fn observed_edges(models: &[TestModel]) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for (test, model) in models.iter().enumerate() {
+        if let (Status::DependsOn, Some(expression)) = (model.status, &model.expression) {
+            let mut dependencies = BTreeSet::new();
+            passing_tests(expression, &mut dependencies);
+            edges.extend(dependencies.into_iter().map(|dependency| Edge { test, dependency }));
+        }
+    }
+    edges
+}
+
+fn passing_tests(expression: &Expression, tests: &mut BTreeSet<usize>) {
+    for operand in &expression.operands {
+        match *operand {
+            Operand::Test(id) => {
+                tests.insert(id as usize);
+            },
+            Operand::Not(_) => {},
+            Operand::Expression(ref inner) => passing_tests(inner, tests),
+        }
+    }
+}
+
+/// Compares what's declared with what was inferred from the matrix. A contradicted edge isn't also reported as
+/// unobserved
+pub fn check(declared: &[Edge], models: &[TestModel], matrix: &ExecutionMatrix) -> Check {
+    let declared: BTreeSet<Edge> = declared.iter().cloned().collect();
+    let observed: BTreeSet<Edge> = observed_edges(models).into_iter().collect();
+
+    let contradicted: Vec<(Edge, usize)> = declared.iter()
+        .filter_map(|edge| {
+            let required = [Constraint::Required { test: edge.test, dependency: edge.dependency }];
+            violations(&required, edge.test, &matrix.distinct_contexts(edge.test)).first()
+                .map(|violation| (*edge, violation.executions))
+        })
+        .collect();
+
+    Check {
+        unobserved: declared.difference(&observed)
+            .filter(|edge| !contradicted.iter().any(|&(contradicted, _)| contradicted == **edge))
+            .cloned()
+            .collect(),
+        undeclared: observed.difference(&declared).cloned().collect(),
+        contradicted,
+    }
+}
+
+pub fn check_json(check: &Check, lookup: &[String]) -> Json {
+    let edge_json = |edge: &Edge| -> BTreeMap<String, Json> {
+        let mut map = BTreeMap::new();
+        map.insert("test".to_owned(), lookup[edge.test].to_json());
+        map.insert("dependency".to_owned(), lookup[edge.dependency].to_json());
+        map
+    };
+
+    let mut map = BTreeMap::new();
+    map.insert(Finding::Unobserved.name().to_owned(), check.unobserved.iter()
+        .map(|edge| Json::Object(edge_json(edge)))
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert(Finding::Undeclared.name().to_owned(), check.undeclared.iter()
+        .map(|edge| Json::Object(edge_json(edge)))
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert(Finding::Contradicted.name().to_owned(), check.contradicted.iter()
+        .map(|&(ref edge, executions)| {
+            let mut json = edge_json(edge);
+            json.insert("executions".to_owned(), executions.to_json());
+            Json::Object(json)
+        })
+        .collect::<Vec<Json>>()
+        .to_json());
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inference::*;
+
+    #[test]
+    fn should_sort_edges_into_findings() {
+        // B passes with C, C with A or B, and D once with nothing else. E never passes
+        let slice: &[&[bool]] = &[
+            &[true, false, false, true],
+            &[false, true, false, true],
+            &[true, true, false, true],
+            &[true, false, true, false],
+            &[false, false, false, false],
+        ];
+        let names: Vec<String> = vec!("A", "B", "C", "D", "E").into_iter().map(String::from).collect();
+        let matrix = ExecutionMatrix::new(slice);
+        let models = infer_all(&matrix, &InferenceOptions::default(), 1);
+        let manifest = parse_constraints("C -> A\nD -> A\nE -> A", &names).unwrap();
+        let declared = declared_edges(&manifest, &names).unwrap();
+
+        let found = check(&declared, &models, &matrix);
+        assert_eq!(found, Check {
+            unobserved: vec!(Edge { test: 4, dependency: 0 }),
+            undeclared: vec!((0, 1), (0, 2), (0, 3), (1, 2), (2, 1), (2, 3)).into_iter()
+                .map(|(test, dependency)| Edge { test, dependency })
+                .collect(),
+            contradicted: vec!((Edge { test: 2, dependency: 0 }, 1), (Edge { test: 3, dependency: 0 }, 1)),
+        });
+        assert_eq!(found.exit_code(&Finding::all()), 7);
+        assert_eq!(found.exit_code(&[Finding::Undeclared]), 2);
+        assert_eq!(Check::default().exit_code(&Finding::all()), 0);
+        assert_eq!(check_json(&found, &names).find_path(&["contradicted"]).unwrap().to_string(),
+                   r#"[{"dependency":"A","executions":1,"test":"C"},{"dependency":"A","executions":1,"test":"D"}]"#);
+    }
+
+    #[test]
+    fn should_only_accept_declared_dependencies() {
+        let names: Vec<String> = vec!("A", "B").into_iter().map(String::from).collect();
+        let manifest = parse_constraints("A !-> B", &names).unwrap();
+        assert_eq!(declared_edges(&manifest, &names),
+                   Err("A manifest can only declare dependencies, found A !-> B".to_owned()));
+        assert_eq!("contradicted".parse(), Ok(Finding::Contradicted));
+    }
+}
@@ -0,0 +1,331 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use execution_matrix::Context;
+use expression::*;
+use infix::*;
+
+/// Something already known about a test's dependencies, which inference honors instead of guessing
+#[derive(Debug, PartialEq, Clone)]
+pub enum Constraint {
+    Required { test: usize, dependency: usize }, // test -> dependency
+    Forbidden { test: usize, dependency: usize }, // test !-> dependency
+    Fixed { test: usize, expression: Expression }, // test = expression
+}
+
+impl Constraint {
+    pub fn test(&self) -> usize {
+        match *self {
+            Constraint::Required { test, .. }
+            | Constraint::Forbidden { test, .. }
+            | Constraint::Fixed { test, .. } => test,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Constraint::Required { .. } => "required",
+            Constraint::Forbidden { .. } => "forbidden",
+            Constraint::Fixed { .. } => "fixed",
+        }
+    }
+
+    /// The constraint as it would be written in an infix constraints file
+    pub fn infix(&self, lookup: &[String]) -> String {
+        let name = |id: usize| expression_infix(&Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Test(id as i32)),
+        }, lookup);
+        match *self {
+            Constraint::Required { test, dependency } => format!("{} -> {}", name(test), name(dependency)),
+            Constraint::Forbidden { test, dependency } => format!("{} !-> {}", name(test), name(dependency)),
+            Constraint::Fixed { test, ref expression } => {
+                format!("{} = {}", name(test), expression_infix(expression, lookup))
+            },
+        }
+    }
+}
+
+/// A constraint that the history disagrees with
+#[derive(Debug, PartialEq, Clone)]
+pub struct Violation {
+    pub constraint: Constraint,
+    pub executions: usize, // Passing executions of the test that disagree
+}
+
+/// Reads constraints, either as JSON or as infix lines like `Paint !-> Engine`, `Engine -> "Gas Lines"` or
+/// `Horn = Battery & Wiring`. Test names are looked up in `lookup`
+pub fn parse_constraints(text: &str, lookup: &[String]) -> Result<Vec<Constraint>, String> {
+    let constraints = if text.trim_start().starts_with('{') {
+        json_constraints(&Json::from_str(text).map_err(|e| e.to_string())?, lookup)?
+    } else {
+        infix_constraints(text, lookup)?
+    };
+
+    for (i, constraint) in constraints.iter().enumerate() {
+        let fixed_before = |test: usize| constraints[..i].iter()
+            .any(|other| other.kind() == "fixed" && other.test() == test);
+        match *constraint {
+            Constraint::Required { test, dependency } | Constraint::Forbidden { test, dependency }
+                if test == dependency => {
+                return Err(format!("A test can't depend on itself: {}", constraint.infix(lookup)));
+            },
+            Constraint::Fixed { test, .. } if fixed_before(test) => {
+                return Err(format!("More than one fixed expression for \"{}\"", lookup[test]));
+            },
+            _ => {},
+        }
+    }
+    Ok(constraints)
+}
+
+/// One constraint per line. Blank lines, and lines starting with `#`, are skipped
+fn infix_constraints(text: &str, lookup: &[String]) -> Result<Vec<Constraint>, String> {
+    let mut constraints = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let at_line = |error: String| format!("Line {}: {}", line_index + 1, error);
+        let tokens = tokens(line).map_err(at_line)?;
+        let test = match tokens.first() {
+            Some(Token::Name(name)) => test_id(name, lookup).map_err(at_line)?,
+            _ => return Err(at_line("Expected a test name first".to_owned())),
+        };
+        let constraint = match (tokens.get(1), &tokens[2.min(tokens.len())..]) {
+            (Some(Token::DependsOn), [Token::Name(dependency)]) => {
+                Constraint::Required { test, dependency: test_id(dependency, lookup).map_err(at_line)? }
+            },
+            (Some(Token::NeverDependsOn), [Token::Name(dependency)]) => {
+                Constraint::Forbidden { test, dependency: test_id(dependency, lookup).map_err(at_line)? }
+            },
+            (Some(Token::Is), expression) => {
+                Constraint::Fixed { test, expression: parse_tokens(expression, lookup).map_err(at_line)? }
+            },
+            _ => return Err(at_line(
+                "Expected \"->\" or \"!->\" and a test name, or \"=\" and an expression".to_owned())),
+        };
+        constraints.push(constraint);
+    }
+    Ok(constraints)
+}
+
+/// `{"required": {"Engine": ["Gas Lines"]}, "forbidden": {"Paint": ["Engine"]}, "fixed": {"Horn": ...}}`, where a
+/// fixed expression is either an operator, as in the output, or an infix string
+fn json_constraints(json: &Json, lookup: &[String]) -> Result<Vec<Constraint>, String> {
+    let section = |key: &str| -> Result<BTreeMap<String, Json>, String> {
+        match json.find(key) {
+            None => Ok(BTreeMap::new()),
+            Some(Json::Object(tests)) => Ok(tests.clone()),
+            Some(other) => Err(format!("Expected \"{}\" to map test names to constraints, found {}", key, other)),
+        }
+    };
+    let dependencies = |name: &str, dependencies: &Json| -> Result<Vec<usize>, String> {
+        dependencies.as_array()
+            .ok_or_else(|| format!("Expected a list of test names for \"{}\", found {}", name, dependencies))?
+            .iter()
+            .map(|dependency| match dependency.as_string() {
+                Some(dependency) => test_id(dependency, lookup),
+                None => Err(format!("Expected a test name, found {}", dependency)),
+            })
+            .collect()
+    };
+
+    let mut constraints = Vec::new();
+    for (name, required) in section("required")? {
+        let test = test_id(&name, lookup)?;
+        for dependency in dependencies(&name, &required)? {
+            constraints.push(Constraint::Required { test, dependency });
+        }
+    }
+    for (name, forbidden) in section("forbidden")? {
+        let test = test_id(&name, lookup)?;
+        for dependency in dependencies(&name, &forbidden)? {
+            constraints.push(Constraint::Forbidden { test, dependency });
+        }
+    }
+    for (name, expression) in section("fixed")? {
+        let expression = match expression.as_string() {
+            Some(text) if test_id(text, lookup).is_err() => parse_infix(text, lookup)?,
+            _ => json_expression(&expression, lookup)?,
+        };
+        constraints.push(Constraint::Fixed { test: test_id(&name, lookup)?, expression });
+    }
+    Ok(constraints)
+}
+
+fn test_id(name: &str, lookup: &[String]) -> Result<usize, String> {
+    lookup.iter()
+        .position(|test| test == name)
+        .ok_or_else(|| format!("Unknown test \"{}\"", name))
+}
+
+/// The expression that a test is known to have, if there is one
+pub fn fixed(constraints: &[Constraint], target_id: usize) -> Option<&Expression> {
+    constraints.iter()
+        .filter_map(|constraint| match *constraint {
+            Constraint::Fixed { test, ref expression } if test == target_id => Some(expression),
+            _ => None,
+        })
+        .next()
+}
+
+/// Tests that the target is known to depend on
+pub fn required(constraints: &[Constraint], target_id: usize) -> Vec<usize> {
+    constraints.iter()
+        .filter_map(|constraint| match *constraint {
+            Constraint::Required { test, dependency } if test == target_id => Some(dependency),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tests whose results say nothing about the target's other dependencies: forbidden ones can never be dependencies,
+/// and required ones are added in afterwards
+pub fn set_aside(constraints: &[Constraint], target_id: usize) -> Vec<usize> {
+    let mut tests: Vec<usize> = constraints.iter()
+        .filter_map(|constraint| match *constraint {
+            Constraint::Required { test, dependency } | Constraint::Forbidden { test, dependency }
+                if test == target_id => Some(dependency),
+            _ => None,
+        })
+        .collect();
+    tests.sort();
+    tests.dedup();
+    tests
+}
+
+/// `required & expression`, or just the required tests without an expression
+pub fn with_required(required: &[usize], expression: Option<&Expression>) -> Expression {
+    let mut operands: Vec<Operand> = required.iter().map(|&id| Operand::Test(id as i32)).collect();
+    match expression {
+        Some(expression) if expression.operator == Operator::And => operands.extend(expression.operands.clone()),
+        Some(expression) if expression.operands.len() == 1 => operands.push(expression.operands[0].clone()),
+        Some(expression) => operands.push(Operand::Expression(expression.clone())),
+        None => {},
+    }
+
+    let operator = if operands.len() == 1 { Operator::Or } else { Operator::And }; // A lone test is an OR
+    Expression { operator, operands }
+}
+
+/// Where the target's passing contexts disagree with what's known about it. A required test failed while the target
+/// passed, a fixed expression wasn't satisfied while it passed, or a forbidden test passed every time it did
+pub fn violations(constraints: &[Constraint], target_id: usize, contexts: &[Context]) -> Vec<Violation> {
+    let disagreeing = |disagrees: &dyn Fn(&Context) -> bool| -> usize {
+        contexts.iter()
+            .filter(|context| disagrees(context))
+            .map(|context| context.executions)
+            .sum()
+    };
+
+    constraints.iter()
+        .filter(|constraint| constraint.test() == target_id)
+        .filter_map(|constraint| {
+            let executions = match *constraint {
+                Constraint::Required { dependency, .. } => disagreeing(&|context| !context.passing.get(dependency)),
+                Constraint::Forbidden { dependency, .. } => {
+                    if contexts.iter().all(|context| context.passing.get(dependency)) {
+                        disagreeing(&|_| true)
+                    } else {
+                        0
+                    }
+                },
+                Constraint::Fixed { ref expression, .. } => disagreeing(&|context| {
+                    let results: Vec<bool> = (0..context.passing.len()).map(|i| context.passing.get(i)).collect();
+                    let variable_to_index: Vec<usize> = (0..results.len()).collect();
+                    !expression.evaluate(&results, &variable_to_index)
+                }),
+            };
+            if executions == 0 {
+                None
+            } else {
+                Some(Violation { constraint: constraint.clone(), executions })
+            }
+        })
+        .collect()
+}
+
+pub fn violation_json(violation: &Violation, lookup: &[String]) -> Json {
+    let mut map = BTreeMap::new();
+    map.insert("test".to_owned(), lookup[violation.constraint.test()].to_json());
+    map.insert("kind".to_owned(), violation.constraint.kind().to_json());
+    map.insert("constraint".to_owned(), violation.constraint.infix(lookup).to_json());
+    map.insert("executions".to_owned(), violation.executions.to_json());
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use execution_matrix::*;
+
+    fn names() -> Vec<String> {
+        vec!("Engine", "Gas Lines", "Paint", "Horn", "Battery").into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn should_read_infix_and_json_alike() {
+        let infix = "# What the mechanics told us\nEngine -> \"Gas Lines\"\n\n\
+                     Paint !-> Engine\nHorn = Battery & Engine\n";
+        let json = r#"{
+            "required": {"Engine": ["Gas Lines"]},
+            "forbidden": {"Paint": ["Engine"]},
+            "fixed": {"Horn": "Battery & Engine"}
+        }"#;
+        let expected = vec!(
+            Constraint::Required { test: 0, dependency: 1 },
+            Constraint::Forbidden { test: 2, dependency: 0 },
+            Constraint::Fixed { test: 3, expression: Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(4), Operand::Test(0)),
+            }},
+        );
+        assert_eq!(parse_constraints(infix, &names()), Ok(expected.clone()));
+        assert_eq!(parse_constraints(json, &names()), Ok(expected.clone()));
+        assert_eq!(expected[0].infix(&names()), "Engine -> \"Gas Lines\"");
+    }
+
+    #[test]
+    fn should_reject_what_cannot_be_honored() {
+        assert_eq!(parse_constraints("Engine -> Wheels", &names()), Err("Line 1: Unknown test \"Wheels\"".to_owned()));
+        assert!(parse_constraints("Engine -> Engine", &names()).is_err());
+        assert!(parse_constraints("Horn = Battery\nHorn = Engine", &names()).is_err());
+        assert!(parse_constraints("Engine -> Paint | Horn", &names()).is_err());
+        assert!(parse_constraints(r#"{"required": ["Engine"]}"#, &names()).is_err());
+    }
+
+    #[test]
+    fn should_find_where_history_disagrees() {
+        // Engine passed twice without Gas Lines, Paint passed only alongside Engine, Horn passed without Battery
+        let slice: &[&[bool]] = &[
+            &[true, true, true, false],
+            &[true, false, false, true],
+            &[true, true, false, false],
+            &[true, false, true, false],
+            &[false, true, true, false],
+        ];
+        let matrix = ExecutionMatrix::new(slice);
+        let constraints = parse_constraints("Engine -> \"Gas Lines\"\nPaint !-> Engine\nHorn = Battery & Engine\n\
+                                             Horn -> Engine", &names()).unwrap();
+
+        let executions: Vec<usize> = (0..slice.len())
+            .flat_map(|test| violations(&constraints, test, &matrix.distinct_contexts(test)))
+            .map(|violation| violation.executions)
+            .collect();
+        assert_eq!(executions, vec!(2, 2, 1));
+        let horn = violations(&constraints, 3, &matrix.distinct_contexts(3));
+        assert_eq!(violation_json(&horn[0], &names()).to_string(),
+                   r#"{"constraint":"Horn = Battery & Engine","executions":1,"kind":"fixed","test":"Horn"}"#);
+    }
+
+    #[test]
+    fn should_put_required_tests_first() {
+        let or = Expression { operator: Operator::Or, operands: vec!(Operand::Test(2), Operand::Test(3)) };
+        assert_eq!(with_required(&[1], Some(&or)), Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(1), Operand::Expression(or.clone())),
+        });
+        assert_eq!(with_required(&[1], None), Expression { operator: Operator::Or, operands: vec!(Operand::Test(1)) });
+    }
+}
@@ -1,4 +1,5 @@
 use expression::*;
+use execution_matrix::*;
 
 pub fn dependency_expression(results: &[&[bool]], target_id: usize) -> Option<Expression> {
     let test_count = results.len();
@@ -40,10 +41,67 @@ pub fn dependency_expression(results: &[&[bool]], target_id: usize) -> Option<Ex
     })
 }
 
+/// Same as `dependency_expression`, but built from deduplicated contexts, after absorbing any that are supersets of
+/// another. The result is smaller, but still reduces to the same thing
+pub fn contexts_dependency_expression(contexts: &[Context]) -> Option<Expression> {
+    let mut subexpressions = Vec::<Operand>::new();
+    for context in minimal_contexts(contexts) {
+        let other_passing: Vec<Operand> = context.passing.ones()
+            .map(|other_id| Operand::Test(other_id as i32))
+            .collect();
+
+        if other_passing.is_empty() {
+            // If a test can pass while all other fails, then it has no external dependencies
+            return None;
+        }
+
+        subexpressions.push(Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: other_passing,
+        }));
+    }
+
+    Some(Expression {
+        operator: Operator::Or,
+        operands: subexpressions
+    })
+}
+
+/// Same as `contexts_dependency_expression`, but the `others` that failed in a context have to fail again, so a test
+/// can depend on another one failing. Contexts no longer absorb each other, so all of them are kept
+pub fn negative_dependency_expression(contexts: &[Context], others: &[usize]) -> Option<Expression> {
+    let mut subexpressions = Vec::<Operand>::new();
+    for context in contexts {
+        let others: Vec<Operand> = others.iter()
+            .map(|&other_id| if context.passing.get(other_id) {
+                Operand::Test(other_id as i32)
+            } else {
+                Operand::Not(other_id as i32)
+            })
+            .collect();
+
+        if others.is_empty() {
+            // With no other tests, there's nothing to depend on
+            return None;
+        }
+
+        subexpressions.push(Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: others,
+        }));
+    }
+
+    Some(Expression {
+        operator: Operator::Or,
+        operands: subexpressions
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use expression::*;
+    use quine_mccluskey::*;
 
     #[test]
     fn should_and_operator_simultaneously_passing() {
@@ -142,4 +200,57 @@ mod tests {
             ),
         }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_reduce_the_same_as_every_execution() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, true, true],
+            &[true, false, false, false, true],
+            &[true, true, true, false, true],
+            &[false, true, false, true, true]
+        ];
+        let matrix = ExecutionMatrix::new(slice);
+
+        for target_id in 0..slice.len() {
+            let contexts = matrix.distinct_contexts(target_id);
+            assert_eq!(contexts_dependency_expression(&contexts).and_then(|expression| reduce(&expression).unwrap()),
+                       dependency_expression(slice, target_id).and_then(|expression| reduce(&expression).unwrap()));
+        }
+        assert_eq!(contexts_dependency_expression(&matrix.distinct_contexts(0)), Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(
+                Operand::Expression(Expression {
+                    operator: Operator::And,
+                    operands: vec!(Operand::Test(1), Operand::Test(2)),
+                }),
+                Operand::Expression(Expression {
+                    operator: Operator::And,
+                    operands: vec!(Operand::Test(3)),
+                }),
+            ),
+        }));
+    }
+
+    #[test]
+    fn should_require_failing_tests_in_negative_mode() {
+        // B passes whenever A fails, and A only passed once, alongside C but not B
+        let slice: &[&[bool]] = &[
+            &[false, true, false, false],
+            &[true, false, false, true],
+            &[false, true, false, true],
+        ];
+        let matrix = ExecutionMatrix::new(slice);
+
+        let b = negative_dependency_expression(&matrix.distinct_contexts(1), &[0, 2]);
+        assert_eq!(b.and_then(|expression| reduce(&expression).unwrap()), Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Not(0)),
+        }));
+        let a = negative_dependency_expression(&matrix.distinct_contexts(0), &[1, 2]);
+        assert_eq!(a.and_then(|expression| reduce(&expression).unwrap()), Some(Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Not(1), Operand::Test(2)),
+        }));
+        assert_eq!(negative_dependency_expression(&matrix.distinct_contexts(0), &[]), None);
+    }
+}
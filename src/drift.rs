@@ -0,0 +1,171 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use execution_matrix::ExecutionMatrix;
+use inference::*;
+
+/// A test's model from one execution on, until the next version
+#[derive(Debug, PartialEq)]
+pub struct Version {
+    pub from: usize, // The first execution that the model holds for
+    pub model: TestModel,
+}
+
+/// Windows of `size` executions, `step` apart, where the last one ends with the last execution
+pub fn windows(execution_count: usize, size: usize, step: usize) -> Vec<Range<usize>> {
+    let size = size.max(1);
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start + size < execution_count {
+        windows.push(start..start + size);
+        start += step.max(1);
+    }
+    windows.push(execution_count.saturating_sub(size)..execution_count);
+    windows
+}
+
+fn infer_range(slices: &[&[bool]], range: Range<usize>, target_id: usize, options: &InferenceOptions) -> TestModel {
+    let window: Vec<&[bool]> = slices.iter().map(|test| &test[range.clone()]).collect();
+    infer(&ExecutionMatrix::new(&window), target_id, options)
+}
+
+/// Where between two windows the test's model changed. Either an execution stops the earlier model from holding as it
+/// is added to the earlier window, or the later model holds as far back as the executions that it was missing
+fn change_point(slices: &[&[bool]], before: &Range<usize>, after: &Range<usize>, target_id: usize,
+                options: &InferenceOptions) -> usize {
+    let previous = infer_range(slices, before.clone(), target_id, options);
+    for end in before.end + 1..=after.end {
+        if !same_model(&infer_range(slices, before.start..end, target_id, options), &previous) {
+            return end - 1;
+        }
+    }
+
+    let next = infer_range(slices, after.clone(), target_id, options);
+    (before.start..after.start)
+        .rev()
+        .take_while(|&start| same_model(&infer_range(slices, start..after.end, target_id, options), &next))
+        .last()
+        .unwrap_or(after.start)
+}
+
+/// Infers every test again in each window, and splits each test's history into the versions of its model. A new
+/// version starts at the execution where the model changed, rather than where the window that noticed it starts
+pub fn drift(slices: &[&[bool]], size: usize, step: usize, options: &InferenceOptions, threads: usize)
+             -> Vec<Vec<Version>> {
+    let execution_count = slices.first().map_or(0, |test| test.len());
+    let windows = windows(execution_count, size, step);
+    let mut timelines: Vec<Vec<Version>> = (0..slices.len()).map(|_| Vec::new()).collect();
+    for (window_index, window) in windows.iter().enumerate() {
+        let sliced: Vec<&[bool]> = slices.iter().map(|test| &test[window.clone()]).collect();
+        let models = infer_all(&ExecutionMatrix::new(&sliced), options, threads);
+        for (target_id, model) in models.into_iter().enumerate() {
+            let timeline = &mut timelines[target_id];
+            let version = match timeline.last() {
+                None => Version { from: 0, model },
+                Some(last) if same_model(&last.model, &model) => continue,
+                Some(last) => {
+                    // Only the executions since the change, so the new version doesn't mix in the old one
+                    let point = change_point(slices, &windows[window_index - 1], window, target_id, options);
+                    let from = point.max(last.from + 1).min(window.end - 1);
+                    let model = infer_range(slices, from..window.end, target_id, options);
+                    if same_model(&last.model, &model) {
+                        continue;
+                    }
+                    Version { from, model }
+                },
+            };
+            timeline.push(version);
+        }
+    }
+    timelines
+}
+
+/// `{"changes": [{"test", "execution", "from", "to", ...metadata}], "timelines": {test: [{"from", "dependency",
+/// ...metadata}]}}`. Each execution is reported by its index in `execution_indexes`, along with its id, commit,
+/// timestamp and any other metadata it has
+pub fn drift_json(timelines: &[Vec<Version>], lookup: &[String], execution_indexes: &[usize],
+                  metadata: &[BTreeMap<String, String>]) -> Json {
+    let cite = |map: &mut BTreeMap<String, Json>, key: &str, from: usize| {
+        map.insert(key.to_owned(), execution_indexes.get(from).cloned().unwrap_or(from).to_json());
+        for (label, value) in metadata.get(from).into_iter().flatten() {
+            map.entry(label.clone()).or_insert_with(|| value.to_json());
+        }
+    };
+
+    let mut changes = Vec::new();
+    for (test, timeline) in timelines.iter().enumerate() {
+        for pair in timeline.windows(2) {
+            let mut change = BTreeMap::new();
+            change.insert("test".to_owned(), lookup[test].to_json());
+            change.insert("from".to_owned(), model_json(&pair[0].model, lookup));
+            change.insert("to".to_owned(), model_json(&pair[1].model, lookup));
+            cite(&mut change, "execution", pair[1].from);
+            changes.push((pair[1].from, test, Json::Object(change)));
+        }
+    }
+    changes.sort_by_key(|&(from, test, _)| (from, test));
+
+    let mut map = BTreeMap::new();
+    map.insert("changes".to_owned(), changes.into_iter()
+        .map(|(_, _, change)| change)
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert("timelines".to_owned(), timelines.iter()
+        .enumerate()
+        .map(|(test, timeline)| (lookup[test].clone(), timeline.iter()
+            .map(|version| {
+                let mut json = BTreeMap::new();
+                cite(&mut json, "from", version.from);
+                json.insert("dependency".to_owned(), model_json(&version.model, lookup));
+                Json::Object(json)
+            })
+            .collect::<Vec<Json>>()
+            .to_json()))
+        .collect::<BTreeMap<String, Json>>()
+        .to_json());
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evidence::Status;
+    use expression::*;
+
+    #[test]
+    fn should_cover_every_execution_with_windows() {
+        assert_eq!(windows(10, 4, 3), vec!(0..4, 3..7, 6..10));
+        assert_eq!(windows(10, 4, 4), vec!(0..4, 4..8, 6..10));
+        assert_eq!(windows(3, 4, 2), vec!(0..3));
+        assert_eq!(windows(0, 4, 2), vec!(0..0));
+    }
+
+    #[test]
+    fn should_find_where_a_dependency_changed() {
+        // Horn needs Battery up to execution 5, and then needs Wiring instead
+        let slice: &[&[bool]] = &[
+            &[true, false, true, true, false, false, true, false, true, false, true, false],
+            &[true, false, false, true, false, true, false, true, false, true, false, true],
+            &[false, true, true, false, true, true, false, true, true, true, false, true],
+        ];
+        let timelines = drift(slice, 4, 2, &InferenceOptions::default(), 1);
+        let horn = &timelines[1];
+        assert_eq!(horn.len(), 2);
+        assert_eq!(horn[0].from, 0);
+        let needs = |id| Some(Expression { operator: Operator::Or, operands: vec!(Operand::Test(id)) });
+        assert_eq!(horn[0].model.expression, needs(0));
+        assert_eq!(horn[1].from, 5);
+        assert_eq!(horn[1].model.expression, needs(2));
+        assert_eq!(horn[1].model.status, Status::DependsOn);
+
+        let lookup = vec!("Battery".to_owned(), "Horn".to_owned(), "Wiring".to_owned());
+        let mut metadata = vec!(BTreeMap::new(); 12);
+        metadata[5].insert("commit".to_owned(), "77b0e3d".to_owned());
+        // As if the history had been filtered down from its 100th execution on
+        let json = drift_json(&timelines, &lookup, &(100..112).collect::<Vec<usize>>(), &metadata);
+        let changes = json.find("changes").and_then(Json::as_array).unwrap();
+        let horn_change = changes.iter().find(|change| change.find("test") == Some(&"Horn".to_json())).unwrap();
+        assert_eq!(horn_change.find("execution"), Some(&105usize.to_json()));
+        assert_eq!(horn_change.find("commit"), Some(&"77b0e3d".to_json()));
+    }
+}
@@ -0,0 +1,201 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::{BTreeMap, BTreeSet};
+use check::{Edge, observed_edges};
+use execution_matrix::ExecutionMatrix;
+use inference::*;
+use test_results::AllTestResults;
+
+/// The models inferred from only the executions with one value of a label, such as `os=linux`
+#[derive(Debug, PartialEq)]
+pub struct Environment {
+    pub value: String,
+    pub executions: usize,
+    pub models: Vec<TestModel>,
+}
+
+/// How the environments' dependencies compare
+#[derive(Debug, PartialEq, Default)]
+pub struct Comparison {
+    pub universal: Vec<Edge>, // Part of the dependency in every environment
+    pub specific: Vec<(Edge, Vec<usize>)>, // Only part of the dependency in some, with the environments it's part of
+    pub differing: Vec<usize>, // Tests whose models aren't the same in every environment
+}
+
+/// The executions with each value of the label, by value. Executions without the label aren't in any of them
+pub fn partition(results: &AllTestResults, key: &str) -> Vec<(String, Vec<usize>)> {
+    let mut partitions = BTreeMap::<String, Vec<usize>>::new();
+    for execution_index in 0..results.execution_count() {
+        if let Some(value) = results.metadata_value(key, execution_index) {
+            partitions.entry(value.to_owned()).or_default().push(execution_index);
+        }
+    }
+    partitions.into_iter().collect()
+}
+
+/// Infers every test separately for each value of the label. Every environment has a model for every test, so the
+/// models line up with `results.names()`
+pub fn infer_environments(results: &AllTestResults, key: &str, options: &InferenceOptions, threads: usize)
+                          -> Vec<Environment> {
+    partition(results, key).into_iter()
+        .map(|(value, executions)| {
+            let others: Vec<usize> = (0..results.execution_count())
+                .filter(|execution_index| !executions.contains(execution_index))
+                .collect();
+            let environment = results.without_executions(&others);
+            let slices = environment.slices();
+            Environment {
+                value,
+                executions: executions.len(),
+                models: infer_all(&ExecutionMatrix::new(&slices), options, threads),
+            }
+        })
+        .collect()
+}
+
+/// Splits the dependency edges into those that every environment has and those that only some have
+pub fn compare(environments: &[Environment]) -> Comparison {
+    let mut found = BTreeMap::<Edge, Vec<usize>>::new();
+    for (environment_index, environment) in environments.iter().enumerate() {
+        for edge in observed_edges(&environment.models) {
+            found.entry(edge).or_default().push(environment_index);
+        }
+    }
+
+    let mut comparison = Comparison::default();
+    for (edge, found_in) in found {
+        if found_in.len() == environments.len() {
+            comparison.universal.push(edge);
+        } else {
+            comparison.specific.push((edge, found_in));
+        }
+    }
+    let test_count = environments.first().map_or(0, |environment| environment.models.len());
+    comparison.differing = (0..test_count)
+        .filter(|&test| environments.windows(2)
+            .any(|pair| !same_model(&pair[0].models[test], &pair[1].models[test])))
+        .collect();
+    comparison
+}
+
+/// `{"label", "environments": {value: executions}, "universal": [{"test", "dependency"}],
+/// "specific": [{"test", "dependency", "environments": [value]}], "differing": [test]}`
+pub fn comparison_json(comparison: &Comparison, environments: &[Environment], key: &str, lookup: &[String]) -> Json {
+    let edge_json = |edge: &Edge| {
+        let mut map = BTreeMap::new();
+        map.insert("test".to_owned(), lookup[edge.test].to_json());
+        map.insert("dependency".to_owned(), lookup[edge.dependency].to_json());
+        map
+    };
+
+    let mut map = BTreeMap::new();
+    map.insert("label".to_owned(), key.to_json());
+    map.insert("environments".to_owned(), environments.iter()
+        .map(|environment| (environment.value.clone(), environment.executions.to_json()))
+        .collect::<BTreeMap<String, Json>>()
+        .to_json());
+    map.insert("universal".to_owned(), comparison.universal.iter()
+        .map(|edge| Json::Object(edge_json(edge)))
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert("specific".to_owned(), comparison.specific.iter()
+        .map(|(edge, found_in)| {
+            let mut json = edge_json(edge);
+            json.insert("environments".to_owned(), found_in.iter()
+                .map(|&environment_index| environments[environment_index].value.clone())
+                .collect::<Vec<String>>()
+                .to_json());
+            Json::Object(json)
+        })
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert("differing".to_owned(), comparison.differing.iter()
+        .map(|&test| lookup[test].clone())
+        .collect::<Vec<String>>()
+        .to_json());
+    Json::Object(map)
+}
+
+/// One model per test. Tests whose models agree everywhere are written as usual, and the others as
+/// `{"conditions": [{"when": {label: value}, "dependency": model}]}`, with environments that agree sharing a condition
+pub fn merged_json(environments: &[Environment], key: &str, lookup: &[String]) -> BTreeMap<String, Json> {
+    let test_count = environments.first().map_or(0, |environment| environment.models.len());
+    (0..test_count)
+        .map(|test| {
+            // Each distinct model, with the environments that inferred it
+            let mut distinct = Vec::<(&TestModel, BTreeSet<&str>)>::new();
+            for environment in environments {
+                let model = &environment.models[test];
+                match distinct.iter_mut().find(|(kept, _)| same_model(kept, model)) {
+                    Some((_, values)) => {
+                        values.insert(&environment.value);
+                    },
+                    None => distinct.push((model, Some(environment.value.as_str()).into_iter().collect())),
+                }
+            }
+
+            let json = if distinct.len() == 1 {
+                model_json(distinct[0].0, lookup)
+            } else {
+                let conditions = distinct.iter()
+                    .map(|(model, values)| {
+                        let mut condition = BTreeMap::new();
+                        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                        let mut when = BTreeMap::new();
+                        let value = if values.len() == 1 { values[0].to_json() } else { values.to_json() };
+                        when.insert(key.to_owned(), value);
+                        condition.insert("when".to_owned(), Json::Object(when));
+                        condition.insert("dependency".to_owned(), model_json(model, lookup));
+                        Json::Object(condition)
+                    })
+                    .collect::<Vec<Json>>();
+                let mut map = BTreeMap::new();
+                map.insert("conditions".to_owned(), conditions.to_json());
+                Json::Object(map)
+            };
+            (lookup[test].clone(), json)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use test_results::parse_results;
+
+    // Horn needs Battery everywhere, but on linux it also needs Daemon. The last execution has no "os" label
+    const HISTORY: &str = "@os,linux,linux,linux,mac,mac,mac,\n\
+                           Battery,1,1,0,1,1,0,1\n\
+                           Daemon,1,0,1,0,0,1,1\n\
+                           Horn,1,0,0,1,0,0,1\n\
+                           Paint,0,0,0,0,0,0,1\n";
+
+    #[test]
+    fn should_infer_each_environment_separately() {
+        let results = parse_results(BufReader::new(HISTORY.as_bytes())).unwrap();
+        assert_eq!(partition(&results, "os"), vec!(("linux".to_owned(), vec!(0, 1, 2)),
+                                                   ("mac".to_owned(), vec!(3, 4, 5))));
+
+        let environments = infer_environments(&results, "os", &InferenceOptions::default(), 1);
+        let comparison = compare(&environments);
+        let horn_needs = |dependency| Edge { test: 2, dependency };
+        assert!(comparison.universal.contains(&horn_needs(0)));
+        assert_eq!(comparison.specific.iter().find(|(edge, _)| *edge == horn_needs(1)),
+                   Some(&(horn_needs(1), vec!(0))));
+        assert!(comparison.differing.contains(&2));
+    }
+
+    #[test]
+    fn should_only_write_conditions_where_environments_differ() {
+        let results = parse_results(BufReader::new(HISTORY.as_bytes())).unwrap();
+        let environments = infer_environments(&results, "os", &InferenceOptions::default(), 1);
+        let lookup = results.names();
+        let merged = merged_json(&environments, "os", &lookup);
+
+        let conditions = merged["Horn"].find("conditions").and_then(Json::as_array).unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].find_path(&["when", "os"]), Some(&"linux".to_json()));
+        assert_eq!(conditions[1].find_path(&["when", "os"]), Some(&"mac".to_json()));
+        assert_eq!(merged["Battery"].find("conditions"), None);
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::BTreeSet;
+use bitset::*;
+use budget::*;
+use expression::*;
+use reducer::*;
+
+/// Rounds of reduce, expand and irredundant to try before settling on a result
+pub const DEFAULT_ITERATIONS: usize = 20;
+
+/// An AND of literals over the variables of one expression: `ones` must be true and `zeros` must be false
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct Cube {
+    ones: BitVector,
+    zeros: BitVector,
+}
+
+impl Cube {
+    /// The cube with no literals, which every assignment is in
+    fn universe(variable_count: usize) -> Cube {
+        Cube { ones: BitVector::new(variable_count), zeros: BitVector::new(variable_count) }
+    }
+
+    fn is_universe(&self) -> bool {
+        self.ones.is_empty() && self.zeros.is_empty()
+    }
+
+    fn literal_count(&self) -> usize {
+        self.ones.count_ones() + self.zeros.count_ones()
+    }
+
+    fn with_literal(&self, variable: usize, value: bool) -> Cube {
+        let mut cube = self.without_literal(variable);
+        if value {
+            cube.ones.set(variable, true);
+        } else {
+            cube.zeros.set(variable, true);
+        }
+        cube
+    }
+
+    fn without_literal(&self, variable: usize) -> Cube {
+        let mut cube = self.clone();
+        cube.ones.set(variable, false);
+        cube.zeros.set(variable, false);
+        cube
+    }
+
+    /// Every assignment in `other` is also in this cube
+    fn contains(&self, other: &Cube) -> bool {
+        self.ones.is_subset(&other.ones) && self.zeros.is_subset(&other.zeros)
+    }
+
+    /// What's left of this cube once the variables that `by` fixes are known. `None` if `by` contradicts it
+    fn cofactor(&self, by: &Cube) -> Option<Cube> {
+        if self.ones.intersects(&by.zeros) || self.zeros.intersects(&by.ones) {
+            return None;
+        }
+
+        let fixed = by.ones.or(&by.zeros);
+        Some(Cube { ones: self.ones.and_not(&fixed), zeros: self.zeros.and_not(&fixed) })
+    }
+
+    /// The smallest cube containing both
+    fn supercube(&self, other: &Cube) -> Cube {
+        Cube { ones: self.ones.and(&other.ones), zeros: self.zeros.and(&other.zeros) }
+    }
+}
+
+/// Whether the cubes cover every assignment, by splitting on the variable that appears most often both ways
+fn tautology(cover: &[Cube], variable_count: usize, budget: &Budget) -> Result<bool, BudgetExceeded> {
+    budget.step()?;
+    let mut cover = cover.to_vec();
+    loop {
+        if cover.iter().any(Cube::is_universe) {
+            return Ok(true);
+        }
+
+        let ones = cover.iter().fold(BitVector::new(variable_count), |all, cube| all.or(&cube.ones));
+        let zeros = cover.iter().fold(BitVector::new(variable_count), |all, cube| all.or(&cube.zeros));
+        let binate = ones.and(&zeros);
+
+        // A variable that only appears one way may as well be set the other way, which drops every cube using it
+        let before = cover.len();
+        cover.retain(|cube| cube.ones.is_subset(&binate) && cube.zeros.is_subset(&binate));
+        if cover.len() == before {
+            break;
+        }
+    }
+
+    let mut counts = vec!(0; variable_count);
+    for cube in &cover {
+        for variable in cube.ones.ones().chain(cube.zeros.ones()) {
+            counts[variable] += 1;
+        }
+    }
+
+    let split = (0..variable_count).filter(|&variable| counts[variable] > 0).max_by_key(|&variable| counts[variable]);
+    match split {
+        None => Ok(false), // Nothing left to cover with
+        Some(variable) => {
+            for &value in &[true, false] {
+                let half = Cube::universe(variable_count).with_literal(variable, value);
+                let cofactors: Vec<Cube> = cover.iter().filter_map(|cube| cube.cofactor(&half)).collect();
+                if !tautology(&cofactors, variable_count, budget)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        },
+    }
+}
+
+/// Whether every assignment in `cube` is in some cube of the cover
+fn covers<'a, I: Iterator<Item = &'a Cube>>(cover: I, cube: &Cube, variable_count: usize, budget: &Budget)
+                                           -> Result<bool, BudgetExceeded> {
+    let cofactors: Vec<Cube> = cover.filter_map(|other| other.cofactor(cube)).collect();
+    tautology(&cofactors, variable_count, budget)
+}
+
+/// Drops as many literals from each cube as the function allows, then the cubes that became contained in another
+fn expand(cover: &[Cube], on_set: &[Cube], variable_count: usize, budget: &Budget)
+          -> Result<Vec<Cube>, BudgetExceeded> {
+    let mut by_size = cover.to_vec();
+    by_size.sort_by_key(Cube::literal_count);
+
+    let mut expanded = Vec::<Cube>::new();
+    for mut cube in by_size {
+        if expanded.iter().any(|other| other.contains(&cube)) {
+            continue;
+        }
+
+        let literals: Vec<usize> = cube.zeros.ones().chain(cube.ones.ones()).collect();
+        for variable in literals {
+            let raised = cube.without_literal(variable);
+            if covers(on_set.iter(), &raised, variable_count, budget)? {
+                cube = raised;
+            }
+        }
+
+        expanded.retain(|other| !cube.contains(other));
+        expanded.push(cube);
+    }
+    Ok(expanded)
+}
+
+/// Drops cubes that the rest of the cover already covers, trying the biggest (most literals) first
+fn irredundant(mut cover: Vec<Cube>, variable_count: usize, budget: &Budget) -> Result<Vec<Cube>, BudgetExceeded> {
+    cover.sort_by(|a, b| b.literal_count().cmp(&a.literal_count()).then(a.cmp(b)));
+
+    let mut i = 0;
+    while i < cover.len() {
+        let redundant = {
+            let others = cover.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, cube)| cube);
+            covers(others, &cover[i], variable_count, budget)?
+        };
+        if redundant {
+            cover.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(cover)
+}
+
+/// Shrinks each cube to just the part that no other cube covers, so the next expand can grow it a different way
+fn reduce(mut cover: Vec<Cube>, variable_count: usize, budget: &Budget) -> Result<Vec<Cube>, BudgetExceeded> {
+    for i in 0..cover.len() {
+        let mut cube = cover[i].clone();
+        // The other cubes, restricted to this one. Trying to halve it on every variable is too slow with hundreds
+        // of them, so only try the variables that one of the others is nothing but
+        let mut rest: Vec<Cube> = cover.iter().enumerate()
+            .filter(|&(j, _)| j != i)
+            .filter_map(|(_, other)| other.cofactor(&cube))
+            .collect();
+        let candidates = rest.iter()
+            .filter(|other| other.literal_count() == 1)
+            .fold(BitVector::new(variable_count), |all, other| all.or(&other.ones).or(&other.zeros));
+
+        for variable in candidates.ones() {
+            for &value in &[true, false] {
+                let other_half = Cube::universe(variable_count).with_literal(variable, !value);
+                let covered: Vec<Cube> = rest.iter().filter_map(|other| other.cofactor(&other_half)).collect();
+                if tautology(&covered, variable_count, budget)? {
+                    let half = Cube::universe(variable_count).with_literal(variable, value);
+                    cube = cube.with_literal(variable, value);
+                    rest = rest.iter().filter_map(|other| other.cofactor(&half)).collect();
+                    break;
+                }
+            }
+        }
+        cover[i] = cube;
+    }
+    Ok(cover)
+}
+
+fn cost(cover: &[Cube]) -> (usize, usize) {
+    (cover.len(), cover.iter().map(Cube::literal_count).sum())
+}
+
+/// Assignments of the function that no single cube can cover two of, so any cover needs a cube for each
+fn lower_bound(cover: &[Cube], variable_count: usize, budget: &Budget) -> Result<usize, BudgetExceeded> {
+    let everything = BitVector::from_bools(&vec!(true; variable_count));
+    let mut points = Vec::<Cube>::new();
+    for cube in cover {
+        let point = Cube { ones: cube.ones.clone(), zeros: everything.and_not(&cube.ones) };
+        let mut compatible = false;
+        for other in &points {
+            if covers(cover.iter(), &other.supercube(&point), variable_count, budget)? {
+                compatible = true;
+                break;
+            }
+        }
+        if !compatible {
+            points.push(point);
+        }
+    }
+    Ok(points.len())
+}
+
+/// Returns the minimized cover and how many iterations it took
+fn espresso(on_set: &[Cube], variable_count: usize, max_iterations: usize, budget: &Budget)
+            -> Result<(Vec<Cube>, usize), BudgetExceeded> {
+    let mut cover = irredundant(expand(on_set, on_set, variable_count, budget)?, variable_count, budget)?;
+    let mut iterations = 0;
+    while iterations < max_iterations {
+        iterations += 1;
+        let reduced = reduce(cover.clone(), variable_count, budget)?;
+        let candidate = irredundant(expand(&reduced, on_set, variable_count, budget)?, variable_count, budget)?;
+        if cost(&candidate) < cost(&cover) {
+            cover = candidate;
+        } else {
+            break;
+        }
+    }
+    Ok((cover, iterations))
+}
+
+/// The expression multiplied out into cubes, over the given (sorted) variables
+fn cubes(expression: &Expression, variables: &[i32]) -> Vec<Cube> {
+    cubes_within(expression, variables, &Budget::unlimited()).unwrap_or_default()
+}
+
+fn cubes_within(expression: &Expression, variables: &[i32], budget: &Budget) -> Result<Vec<Cube>, BudgetExceeded> {
+    Ok(sum_of_products_within(expression, budget)?.iter()
+        .map(|term| {
+            let mut cube = Cube::universe(variables.len());
+            for literal in term {
+                cube = cube.with_literal(variables.binary_search(&literal.test).unwrap(), !literal.negated);
+            }
+            cube
+        })
+        .collect())
+}
+
+/// Whether every set of results that satisfies `a` also satisfies `b`, giving up once the budget runs out
+pub fn implies(a: &Expression, b: &Expression, budget: &Budget) -> Result<bool, BudgetExceeded> {
+    let mut variables = a.variables();
+    variables.extend(b.variables());
+    variables.sort();
+    variables.dedup();
+
+    let b_cubes = cubes_within(b, &variables, budget)?;
+    for cube in cubes_within(a, &variables, budget)? {
+        if !covers(b_cubes.iter(), &cube, variables.len(), budget)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The result of a heuristic minimization
+#[derive(Debug, PartialEq)]
+pub struct Minimized {
+    pub expression: Option<Expression>,
+    pub lower_bound: usize, // No expression can have fewer AND terms than this
+    pub terms: usize,
+    pub iterations: usize,
+}
+
+impl Minimized {
+    /// How many more AND terms the result has than it might need
+    pub fn gap(&self) -> usize {
+        self.terms - self.lower_bound
+    }
+}
+
+pub fn minimize(expression: &Expression, max_iterations: usize) -> Minimized {
+    minimize_within(expression, max_iterations, &Budget::unlimited()).unwrap()
+}
+
+/// `minimize`, giving up once the budget runs out
+pub fn minimize_within(expression: &Expression, max_iterations: usize, budget: &Budget)
+                       -> Result<Minimized, BudgetExceeded> {
+    let mut variables = expression.variables();
+    variables.sort();
+    let variable_count = variables.len();
+
+    let on_set = cubes(expression, &variables);
+    let (cover, iterations) = espresso(&on_set, variable_count, max_iterations, budget)?;
+
+    // Expanding always drops a negative literal that a positive function doesn't need, so for those only `ones` are
+    // left
+    let mut terms: Vec<BTreeSet<Literal>> = cover.iter()
+        .map(|cube| {
+            let ones = cube.ones.ones().map(|variable| Literal { test: variables[variable], negated: false });
+            let zeros = cube.zeros.ones().map(|variable| Literal { test: variables[variable], negated: true });
+            ones.chain(zeros).collect()
+        })
+        .collect();
+    terms.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+    Ok(Minimized {
+        expression: terms_expression(&terms),
+        lower_bound: lower_bound(&cover, variable_count, budget)?,
+        terms: cover.len(),
+        iterations,
+    })
+}
+
+/// Heuristic minimization in the style of Espresso, for dependencies too wide to reduce exactly
+pub struct Espresso {
+    pub max_iterations: usize,
+}
+
+impl Reducer for Espresso {
+    fn name(&self) -> &'static str {
+        "espresso"
+    }
+
+    fn reduce(&self, expression: &Expression) -> Option<Expression> {
+        minimize(expression, self.max_iterations).expression
+    }
+
+    fn reduce_bounded(&self, expression: &Expression) -> (Option<Expression>, Option<usize>) {
+        self.reduce_within(expression, &Budget::unlimited()).unwrap()
+    }
+
+    fn reduce_within(&self, expression: &Expression, budget: &Budget)
+                     -> Result<(Option<Expression>, Option<usize>), BudgetExceeded> {
+        let minimized = minimize_within(expression, self.max_iterations, budget)?;
+        let gap = minimized.gap();
+        Ok((minimized.expression, Some(gap)))
+    }
+
+    fn reduce_all_within(&self, expression: &Expression, budget: &Budget) -> Result<Vec<Expression>, BudgetExceeded> {
+        Ok(self.reduce_within(expression, budget)?.0.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(ones: &[usize], zeros: &[usize], variable_count: usize) -> Cube {
+        let mut cube = Cube::universe(variable_count);
+        for &variable in ones {
+            cube = cube.with_literal(variable, true);
+        }
+        for &variable in zeros {
+            cube = cube.with_literal(variable, false);
+        }
+        cube
+    }
+
+    fn and(ids: &[i32]) -> Operand {
+        Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        })
+    }
+
+    fn or(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::Or, operands }
+    }
+
+    #[test]
+    fn should_only_find_tautologies_that_cover_everything() {
+        let tautology = |cover: &[Cube]| super::tautology(cover, 2, &Budget::unlimited()).unwrap();
+        assert!(tautology(&[cube(&[0], &[], 2), cube(&[], &[0], 2)]));
+        assert!(!tautology(&[cube(&[0], &[], 2), cube(&[1], &[], 2)]));
+        assert!(tautology(&[cube(&[0, 1], &[], 2), cube(&[], &[0], 2), cube(&[], &[1], 2)]));
+        assert!(!tautology(&[cube(&[0, 1], &[], 2), cube(&[], &[0], 2)]));
+        assert!(!tautology(&[]));
+    }
+
+    #[test]
+    fn should_merge_cubes_that_only_differ_in_one_literal() {
+        let on_set = vec!(cube(&[0, 1], &[], 3), cube(&[0], &[1], 3), cube(&[1, 2], &[0], 3));
+        let (cover, _) = espresso(&on_set, 3, DEFAULT_ITERATIONS, &Budget::unlimited()).unwrap();
+        assert_eq!(cover, vec!(cube(&[1, 2], &[], 3), cube(&[0], &[], 3)));
+        assert_eq!(lower_bound(&cover, 3, &Budget::unlimited()).unwrap(), 2);
+    }
+
+    #[test]
+    fn should_match_exact_reduction_for_dependencies() {
+        let expressions = vec!(
+            or(vec!(and(&[1, 2]), and(&[2, 3]), and(&[3]), and(&[1, 2, 3]), and(&[1, 4]), and(&[4, 1, 5]))),
+            or(vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2]))),
+            or(vec!(and(&[0, 2]), and(&[0, 2, 3]))),
+        );
+
+        for expression in &expressions {
+            let minimized = minimize(expression, DEFAULT_ITERATIONS);
+            assert_eq!(minimized.expression, QuineMcCluskey.reduce(expression));
+            assert_eq!(minimized.gap(), 0);
+        }
+    }
+
+    #[test]
+    fn should_handle_hundreds_of_variables() {
+        let terms: Vec<Operand> = (0..200)
+            .map(|i| and(&[i, i + 1, (i * 7) % 300]))
+            .chain((0..100).map(|i| and(&[i, i + 1])))
+            .collect();
+        let expression = or(terms);
+
+        let minimized = minimize(&expression, DEFAULT_ITERATIONS);
+        assert_eq!(minimized.expression, Absorption.reduce(&expression));
+        assert_eq!(minimized.gap(), 0);
+    }
+
+    #[test]
+    fn should_stop_at_the_iteration_and_step_budgets() {
+        let expression = or(vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2])));
+        assert_eq!(minimize(&expression, 0).iterations, 0);
+        assert!(minimize(&expression, 1).iterations <= 1);
+
+        let budget = Budget::start(&BudgetLimits { time: None, steps: Some(5) });
+        assert_eq!(minimize_within(&expression, DEFAULT_ITERATIONS, &budget), Err(BudgetExceeded::Steps));
+    }
+}
@@ -0,0 +1,120 @@
+use execution_matrix::Context;
+
+/// How much a test's history says about its dependencies
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Status {
+    // Passed while every other test failed
+    Independent,
+    DependsOn,
+    // Passed, but not in enough different situations to say what it needs
+    InsufficientEvidence,
+    NeverPassed,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Status::Independent => "independent",
+            Status::DependsOn => "depends-on",
+            Status::InsufficientEvidence => "insufficient-evidence",
+            Status::NeverPassed => "never-passed",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Evidence {
+    pub passing_executions: usize,
+    pub passing_contexts: usize, // distinct sets of other passing tests that the target passed with
+    pub informative_contexts: usize, // passing contexts where at least one other test failed
+    pub independent: bool, // passed in at least one execution where every other test failed
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EvidenceThresholds {
+    pub min_passing_executions: usize,
+    pub min_passing_contexts: usize,
+}
+
+impl Default for EvidenceThresholds {
+    fn default() -> EvidenceThresholds {
+        EvidenceThresholds { min_passing_executions: 1, min_passing_contexts: 1 }
+    }
+}
+
+/// Gathers evidence from a target's distinct passing contexts, out of `test_count` tests in total
+pub fn evidence(contexts: &[Context], test_count: usize) -> Evidence {
+    let counts: Vec<usize> = contexts.iter().map(|context| context.passing.count_ones()).collect();
+
+    Evidence {
+        passing_executions: contexts.iter().map(|context| context.executions).sum(),
+        passing_contexts: contexts.len(),
+        informative_contexts: counts.iter()
+            .filter(|&&count| count < test_count - 1)
+            .count(),
+        independent: counts.contains(&0),
+    }
+}
+
+pub fn status(evidence: &Evidence, thresholds: &EvidenceThresholds) -> Status {
+    if evidence.passing_executions == 0 {
+        Status::NeverPassed
+    } else if evidence.independent {
+        Status::Independent
+    } else if evidence.informative_contexts == 0
+        || evidence.passing_executions < thresholds.min_passing_executions
+        || evidence.passing_contexts < thresholds.min_passing_contexts {
+        // If the target only passed when everything else passed, then every other test is a "dependency"
+        Status::InsufficientEvidence
+    } else {
+        Status::DependsOn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use execution_matrix::*;
+
+    fn evidence(slice: &[&[bool]], target_id: usize) -> Evidence {
+        super::evidence(&ExecutionMatrix::new(slice).distinct_contexts(target_id), slice.len())
+    }
+
+    #[test]
+    fn should_count_distinct_passing_contexts() {
+        let slice: &[&[bool]] = &[&[true, true, true, false], &[true, false, true, true], &[true, true, true, true]];
+        assert_eq!(evidence(slice, 0), Evidence {
+            passing_executions: 3,
+            passing_contexts: 2,
+            informative_contexts: 1,
+            independent: false,
+        });
+    }
+
+    #[test]
+    fn should_distinguish_statuses() {
+        let thresholds = EvidenceThresholds::default();
+        let slice: &[&[bool]] = &[
+            &[false, false, false],
+            &[true, false, false],
+            &[false, true, false],
+            &[false, true, true],
+        ];
+        assert_eq!(status(&evidence(slice, 0), &thresholds), Status::NeverPassed);
+        assert_eq!(status(&evidence(slice, 1), &thresholds), Status::Independent);
+        assert_eq!(status(&evidence(slice, 2), &thresholds), Status::DependsOn);
+
+        let slice: &[&[bool]] = &[&[false, true], &[true, true], &[true, true]];
+        assert_eq!(status(&evidence(slice, 0), &thresholds), Status::InsufficientEvidence);
+    }
+
+    #[test]
+    fn should_require_minimum_passing_contexts() {
+        let slice: &[&[bool]] = &[&[true, true], &[true, false], &[false, true]];
+        assert_eq!(status(&evidence(slice, 0), &EvidenceThresholds::default()), Status::DependsOn);
+        assert_eq!(status(&evidence(slice, 0), &EvidenceThresholds {
+            min_passing_executions: 1,
+            min_passing_contexts: 3,
+        }), Status::InsufficientEvidence);
+    }
+}
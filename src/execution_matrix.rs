@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use bitset::*;
+
+/// A distinct set of other tests that a target passed alongside, and how many executions it happened in
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Context {
+    pub passing: BitVector,
+    pub executions: usize,
+}
+
+/// Test results packed into bits, one row per execution
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExecutionMatrix {
+    pub test_count: usize,
+    pub execution_count: usize,
+    executions: Vec<BitVector>, // One bit per test, for each execution
+}
+
+impl ExecutionMatrix {
+    pub fn new(results: &[&[bool]]) -> ExecutionMatrix {
+        let test_count = results.len();
+        let execution_count = results.first().map_or(0, |test| test.len());
+        let mut executions = vec!(BitVector::new(test_count); execution_count);
+
+        for (test_id, test) in results.iter().enumerate() {
+            for (execution_index, &passed) in test.iter().enumerate() {
+                if passed {
+                    executions[execution_index].set(test_id, true);
+                }
+            }
+        }
+
+        ExecutionMatrix {
+            test_count,
+            execution_count,
+            executions,
+        }
+    }
+
+    /// The tests that passed in the execution
+    pub fn execution(&self, execution_index: usize) -> &BitVector {
+        &self.executions[execution_index]
+    }
+
+    /// For every execution that the target passed in, the other tests that passed alongside it
+    pub fn passing_contexts(&self, target_id: usize) -> Vec<BitVector> {
+        let mut others = BitVector::from_bools(&vec!(true; self.test_count));
+        others.set(target_id, false);
+
+        self.executions.iter()
+            .filter(|execution| execution.get(target_id))
+            .map(|execution| execution.and(&others))
+            .collect()
+    }
+
+    /// `passing_contexts`, with identical contexts collapsed together
+    pub fn distinct_contexts(&self, target_id: usize) -> Vec<Context> {
+        let mut counts = BTreeMap::<BitVector, usize>::new();
+        for context in self.passing_contexts(target_id) {
+            *counts.entry(context).or_insert(0) += 1;
+        }
+
+        counts.into_iter()
+            .map(|(passing, executions)| Context { passing, executions })
+            .collect()
+    }
+}
+
+/// Drops every context that's a superset of another one, since `X | (X & Y)` is just `X`
+pub fn minimal_contexts(contexts: &[Context]) -> Vec<&Context> {
+    let mut by_size: Vec<&Context> = contexts.iter().collect();
+    by_size.sort_by_key(|context| context.passing.count_ones());
+
+    let mut minimal = Vec::<&Context>::new();
+    for context in by_size {
+        if !minimal.iter().any(|kept| kept.passing.is_subset(&context.passing)) {
+            minimal.push(context);
+        }
+    }
+
+    minimal.sort_by(|a, b| a.passing.cmp(&b.passing));
+    minimal
+}
+
+/// The contexts as if the given tests had never been run, with any that become identical collapsed together
+pub fn without_tests(contexts: &[Context], tests: &[usize]) -> Vec<Context> {
+    let mut counts = BTreeMap::<BitVector, usize>::new();
+    for context in contexts {
+        let mut passing = context.passing.clone();
+        for &test in tests {
+            passing.set(test, false);
+        }
+        *counts.entry(passing).or_insert(0) += context.executions;
+    }
+
+    counts.into_iter()
+        .map(|(passing, executions)| Context { passing, executions })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pack_tests_and_executions() {
+        let slice: &[&[bool]] = &[&[true, false, true], &[false, false, true]];
+        let matrix = ExecutionMatrix::new(slice);
+        assert_eq!(matrix.test_count, 2);
+        assert_eq!(matrix.execution_count, 3);
+        assert_eq!(matrix.passing_contexts(1), vec!(BitVector::from_bools(&[true, false])));
+    }
+
+    #[test]
+    fn should_count_identical_contexts_once() {
+        let slice: &[&[bool]] = &[&[true, true, true, true], &[true, false, true, false], &[true, true, true, true]];
+        let matrix = ExecutionMatrix::new(slice);
+        assert_eq!(matrix.distinct_contexts(0), vec!(
+            Context { passing: BitVector::from_bools(&[false, false, true]), executions: 2 },
+            Context { passing: BitVector::from_bools(&[false, true, true]), executions: 2 },
+        ));
+    }
+
+    #[test]
+    fn should_absorb_supersets() {
+        let context = |bits: &[bool]| Context { passing: BitVector::from_bools(bits), executions: 1 };
+        let contexts = vec!(
+            context(&[true, true, false]),
+            context(&[true, false, false]),
+            context(&[false, true, true]),
+            context(&[true, false, true]),
+        );
+        assert_eq!(minimal_contexts(&contexts), vec!(&contexts[1], &contexts[2]));
+    }
+
+    #[test]
+    fn should_merge_contexts_that_only_differ_in_left_out_tests() {
+        let context = |bits: &[bool], executions| Context { passing: BitVector::from_bools(bits), executions };
+        let contexts = vec!(
+            context(&[true, true, false], 1),
+            context(&[true, false, false], 2),
+            context(&[false, true, true], 1),
+        );
+        assert_eq!(without_tests(&contexts, &[1]), vec!(
+            context(&[true, false, false], 3),
+            context(&[false, false, true], 1),
+        ));
+    }
+
+    #[test]
+    fn should_leave_target_out_of_its_passing_contexts() {
+        let slice: &[&[bool]] = &[&[true, false, true], &[false, false, true], &[true, true, true]];
+        let matrix = ExecutionMatrix::new(slice);
+        assert_eq!(matrix.passing_contexts(0), vec!(
+            BitVector::from_bools(&[false, false, true]),
+            BitVector::from_bools(&[false, true, true]),
+        ));
+    }
+}
@@ -0,0 +1,417 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use std::slice;
+use bitset::BitVector;
+use execution_matrix::*;
+use expression::*;
+
+// Each hypothesis is a truth table with a bit for each of the 2^n assignments of its candidates
+pub const MAX_CANDIDATES: usize = 10;
+
+// Above this many candidates, experiments only break one or two of them, or all but one or two, rather than every
+// combination
+const MAX_COMBINED_CANDIDATES: usize = 6;
+
+/// Every monotone dependency of one test that agrees with its history
+#[derive(Debug, PartialEq)]
+pub struct Hypotheses {
+    pub target: usize,
+    pub candidates: Vec<i32>, // Tests in the smallest sets of others that the target passed alongside
+    pub pruned: usize, // Candidates left out to stay within `MAX_CANDIDATES`, and assumed to pass
+    pub tables: Vec<BitVector>, // bit `m` is set when the target passes with exactly candidates `m` passing
+    pub truncated: bool,
+}
+
+/// A set of tests to deliberately break, while every other test is left working
+#[derive(Debug, PartialEq, Clone)]
+pub struct Experiment {
+    pub force_fail: Vec<usize>,
+    pub expected_remaining: f64, // Expected hypotheses left per test, once every experiment so far has been run
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Skipped {
+    NeverPassed,
+    Independent,
+}
+
+pub fn hypotheses(results: &[&[bool]], target_id: usize, max_hypotheses: usize) -> Result<Hypotheses, Skipped> {
+    // A test that only ever passed alongside a smaller passing context can't change which hypotheses fit, so only the
+    // minimal contexts give candidates
+    let contexts = ExecutionMatrix::new(results).distinct_contexts(target_id);
+    let minimal = minimal_contexts(&contexts);
+    if minimal.is_empty() {
+        return Err(Skipped::NeverPassed);
+    }
+    if minimal.iter().any(|context| context.passing.is_empty()) {
+        return Err(Skipped::Independent);
+    }
+
+    // The tests in the most minimal contexts are kept, since they're the likeliest to be needed
+    let mut counts = BTreeMap::<usize, usize>::new();
+    for context in &minimal {
+        for test in context.passing.ones() {
+            *counts.entry(test).or_insert(0) += 1;
+        }
+    }
+    let mut ranked: Vec<(usize, usize)> = counts.into_iter().collect();
+    ranked.sort_by_key(|&(test, count)| (usize::MAX - count, test));
+    let pruned = ranked.len().saturating_sub(MAX_CANDIDATES);
+    let mut candidates: Vec<i32> = ranked.iter().take(MAX_CANDIDATES).map(|&(test, _)| test as i32).collect();
+    candidates.sort();
+
+    // Every assignment where the target has been seen passing, and everything above it. Pruned candidates pass
+    let points = 1usize << candidates.len();
+    let seen: Vec<usize> = minimal.iter()
+        .map(|context| candidates.iter()
+            .enumerate()
+            .filter(|&(_, &candidate)| context.passing.get(candidate as usize))
+            .fold(0, |point, (i, _)| point | (1 << i)))
+        .collect();
+    let mut known_table = BitVector::new(points);
+    for point in 0..points {
+        if seen.iter().any(|&context| context & !point == 0) {
+            known_table.set(point, true);
+        }
+    }
+
+    // Decide assignments from most to fewest passing candidates, so supersets are always decided first
+    let mut order: Vec<usize> = (0..points).collect();
+    order.sort_by_key(|&point| (usize::MAX - (point as u64).count_ones() as usize, point));
+
+    let (tables, truncated) = enumerate(&order, &known_table, candidates.len(), max_hypotheses);
+    Ok(Hypotheses {
+        target: target_id,
+        candidates,
+        pruned,
+        tables,
+        truncated,
+    })
+}
+
+// Every table in `order`, with whether it stopped early because `max_hypotheses` was reached. Passing is tried before
+// failing at each undecided assignment, and the failing branches wait on a stack rather than the call stack
+fn enumerate(order: &[usize], known: &BitVector, variable_count: usize, max_hypotheses: usize)
+             -> (Vec<BitVector>, bool) {
+    let mut tables = Vec::new();
+    let mut pending = vec!((0, BitVector::new(known.len())));
+    while let Some((mut position, mut table)) = pending.pop() {
+        while position < order.len() {
+            let point = order[position];
+            let superset_fails = (0..variable_count)
+                .filter(|&i| (point >> i) & 1 == 0)
+                .any(|i| !table.get(point | (1 << i)));
+
+            if known.get(point) {
+                table.set(point, true);
+            } else if !superset_fails {
+                pending.push((position + 1, table.clone()));
+                table.set(point, true);
+            }
+            position += 1;
+        }
+
+        if tables.len() >= max_hypotheses {
+            return (tables, true);
+        }
+        tables.push(table);
+    }
+    (tables, false)
+}
+
+/// The smallest OR-of-ANDs for a hypothesis: one AND per minimal passing assignment
+pub fn hypothesis_expression(table: &BitVector, candidates: &[i32]) -> Option<Expression> {
+    let minimal: Vec<usize> = table.ones()
+        .filter(|&point| !(0..candidates.len())
+            .filter(|&i| (point >> i) & 1 == 1)
+            .any(|i| table.get(point & !(1 << i))))
+        .collect();
+
+    if minimal.contains(&0) {
+        return None; // Passes with nothing else passing
+    }
+
+    Some(Expression {
+        operator: Operator::Or,
+        operands: minimal.iter()
+            .map(|&point| Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: (0..candidates.len())
+                    .filter(|&i| (point >> i) & 1 == 1)
+                    .map(|i| Operand::Test(candidates[i]))
+                    .collect(),
+            }))
+            .collect(),
+    })
+}
+
+// The assignment of a hypothesis' candidates left once the given tests are made to fail, or `None` if the target
+// itself is made to fail
+fn experiment_point(hypotheses: &Hypotheses, force_fail: &[usize]) -> Option<usize> {
+    if force_fail.contains(&hypotheses.target) {
+        return None;
+    }
+
+    let point = hypotheses.candidates.iter()
+        .enumerate()
+        .filter(|&(_, &candidate)| !force_fail.contains(&(candidate as usize)))
+        .fold(0usize, |point, (i, _)| point | (1 << i));
+    Some(point)
+}
+
+// The hypotheses of one test, in classes that the experiments chosen so far can't tell apart
+struct Split<'a> {
+    hypotheses: &'a Hypotheses,
+    classes: Vec<usize>, // Each table's class
+    class_count: usize,
+}
+
+impl<'a> Split<'a> {
+    fn new(hypotheses: &'a Hypotheses) -> Split<'a> {
+        Split { hypotheses, classes: vec!(0; hypotheses.tables.len()), class_count: 1 }
+    }
+
+    // Where the experiment's outcome tells something, as a table index. Every hypothesis agrees when the target can't
+    // run, or when all of its candidates pass
+    fn point(&self, force_fail: &[usize]) -> Option<usize> {
+        let everything = (1usize << self.hypotheses.candidates.len()) - 1;
+        experiment_point(self.hypotheses, force_fail).filter(|&point| point != everything)
+    }
+
+    // Each table's class once the experiment is run too, as `class * 2 + outcome`
+    fn split_class(&self, table: usize, point: Option<usize>) -> usize {
+        self.classes[table] * 2 + point.is_some_and(|point| self.hypotheses.tables[table].get(point)) as usize
+    }
+
+    // Expected number of hypotheses still standing after also running the experiment
+    fn remaining(&self, force_fail: &[usize]) -> f64 {
+        let point = self.point(force_fail);
+        let mut sizes = vec!(0usize; self.class_count * 2);
+        for table in 0..self.classes.len() {
+            sizes[self.split_class(table, point)] += 1;
+        }
+        let total = self.classes.len() as f64;
+        sizes.iter().map(|&size| (size * size) as f64).sum::<f64>() / (total * total) * total
+    }
+
+    fn add(&mut self, force_fail: &[usize]) {
+        let point = self.point(force_fail);
+        let mut renumbered = vec!(None; self.class_count * 2);
+        let mut class_count = 0;
+        for table in 0..self.classes.len() {
+            let split = self.split_class(table, point);
+            self.classes[table] = *renumbered[split].get_or_insert_with(|| {
+                class_count += 1;
+                class_count - 1
+            });
+        }
+        self.class_count = class_count;
+    }
+}
+
+/// Greedily picks the experiments that split the hypotheses of every given test the most
+pub fn plan_experiments(all_hypotheses: &[Hypotheses], count: usize) -> Vec<Experiment> {
+    if all_hypotheses.is_empty() {
+        return Vec::new();
+    }
+
+    let mut candidates = Vec::<Vec<usize>>::new();
+    for hypotheses in all_hypotheses {
+        let count = hypotheses.candidates.len();
+        for passing in 0..(1usize << count) {
+            let broken = count - (passing as u64).count_ones() as usize;
+            if count > MAX_COMBINED_CANDIDATES && broken > 2 && broken + 2 < count {
+                continue;
+            }
+            let mut force_fail: Vec<usize> = hypotheses.candidates.iter()
+                .enumerate()
+                .filter(|&(i, _)| (passing >> i) & 1 == 0)
+                .map(|(_, &candidate)| candidate as usize)
+                .collect();
+            force_fail.sort();
+            if !force_fail.is_empty() && !candidates.contains(&force_fail) {
+                candidates.push(force_fail);
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+    let mut splits: Vec<Split> = all_hypotheses.iter().map(Split::new).collect();
+    let score = |splits: &[Split], candidate: &[usize]| -> f64 {
+        splits.iter().map(|split| split.remaining(candidate)).sum::<f64>() / splits.len() as f64
+    };
+
+    let mut chosen = Vec::<Vec<usize>>::new();
+    let mut experiments = Vec::<Experiment>::new();
+    let mut current = score(&splits, &[]);
+    while experiments.len() < count {
+        let mut best: Option<(f64, &Vec<usize>)> = None;
+        for candidate in &candidates {
+            if chosen.contains(candidate) {
+                continue;
+            }
+            let candidate_score = score(&splits, candidate);
+            if best.is_none_or(|(best_score, _)| candidate_score < best_score) {
+                best = Some((candidate_score, candidate));
+            }
+        }
+
+        match best {
+            Some((best_score, candidate)) if best_score < current => {
+                chosen.push(candidate.clone());
+                for split in &mut splits {
+                    split.add(candidate);
+                }
+                current = best_score;
+                experiments.push(Experiment { force_fail: candidate.clone(), expected_remaining: best_score });
+            },
+            _ => break, // Nothing left can tell the remaining hypotheses apart
+        }
+    }
+    experiments
+}
+
+fn experiments_json(experiments: &[Experiment], lookup: &[String]) -> Json {
+    experiments.iter()
+        .map(|experiment| {
+            let mut map = BTreeMap::new();
+            map.insert("force_fail".to_owned(), experiment.force_fail.iter()
+                .map(|&id| lookup[id].clone())
+                .collect::<Vec<String>>()
+                .to_json());
+            map.insert("expected_remaining".to_owned(), experiment.expected_remaining.to_json());
+            Json::Object(map)
+        })
+        .collect::<Vec<Json>>()
+        .to_json()
+}
+
+/// Suggests experiments for the whole suite, and for each test on its own
+pub fn suggestions_json(results: &[&[bool]], lookup: &[String], count: usize, max_hypotheses: usize) -> Json {
+    let mut tests = BTreeMap::new();
+    let mut all_hypotheses = Vec::new();
+
+    for target_id in 0..results.len() {
+        let mut map = BTreeMap::new();
+        match hypotheses(results, target_id, max_hypotheses) {
+            Ok(hypotheses) => {
+                map.insert("hypotheses".to_owned(), hypotheses.tables.len().to_json());
+                map.insert("truncated".to_owned(), hypotheses.truncated.to_json());
+                if hypotheses.pruned > 0 {
+                    map.insert("pruned_candidates".to_owned(), hypotheses.pruned.to_json());
+                }
+                if hypotheses.tables.len() <= 10 {
+                    map.insert("consistent".to_owned(), hypotheses.tables.iter()
+                        .map(|table| hypothesis_expression(table, &hypotheses.candidates)
+                            .map_or(Json::Null, |expression| expression_json(&expression, lookup)))
+                        .collect::<Vec<Json>>()
+                        .to_json());
+                }
+                if hypotheses.tables.len() > 1 {
+                    let experiments = plan_experiments(slice::from_ref(&hypotheses), count);
+                    map.insert("experiments".to_owned(), experiments_json(&experiments, lookup));
+                    all_hypotheses.push(hypotheses);
+                }
+            },
+            Err(skipped) => {
+                map.insert("skipped".to_owned(), match skipped {
+                    Skipped::NeverPassed => "never-passed",
+                    Skipped::Independent => "independent",
+                }.to_json());
+            },
+        }
+        tests.insert(lookup[target_id].clone(), Json::Object(map));
+    }
+
+    let mut map = BTreeMap::new();
+    map.insert("suite".to_owned(), experiments_json(&plan_experiments(&all_hypotheses, count), lookup));
+    map.insert("tests".to_owned(), Json::Object(tests));
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_enumerate_monotone_hypotheses_above_history() {
+        // Test 0 has only passed alongside both 1 and 2: it could need both, either, one in particular, or neither
+        let slice: &[&[bool]] = &[&[true, false], &[true, true], &[true, false]];
+        let result = hypotheses(slice, 0, 100).unwrap();
+        assert_eq!(result.candidates, vec!(1, 2));
+        assert_eq!(result.tables.len(), 5);
+        assert!(!result.truncated);
+        assert!(result.tables.iter().all(|table| table.get(3)));
+    }
+
+    #[test]
+    fn should_only_take_candidates_from_minimal_contexts() {
+        // Test 0 passed alongside 1 alone, and alongside every one of 1 to 9 together
+        let mut slice: Vec<&[bool]> = vec!(&[true, true, false]);
+        slice.push(&[true, true, true]);
+        slice.extend((2..10).map(|_| &[false, true, true][..]));
+        let result = hypotheses(&slice, 0, 100).unwrap();
+        assert_eq!(result.candidates, vec!(1));
+        assert_eq!(result.tables.len(), 2); // Needs 1, or needs nothing
+
+        // Test 1's minimal contexts are 0 alone and 2 to 9 together, which is more than six candidates
+        let result = hypotheses(&slice, 1, 100).unwrap();
+        assert_eq!(result.candidates.len(), 9);
+        assert!(result.truncated);
+    }
+
+    #[test]
+    fn should_prune_candidates_in_the_fewest_minimal_contexts() {
+        // Test 0 passed alongside 1 and each of 2 to 14 in turn, so 1 is in every minimal context
+        let rows: Vec<Vec<bool>> = (0..15)
+            .map(|test| (0..13).map(|execution| test < 2 || test == execution + 2).collect())
+            .collect();
+        let slice: Vec<&[bool]> = rows.iter().map(|row| &row[..]).collect();
+        let result = hypotheses(&slice, 0, 10).unwrap();
+        assert_eq!(result.candidates.len(), MAX_CANDIDATES);
+        assert_eq!(result.candidates[0], 1);
+        assert_eq!(result.pruned, 13 + 1 - MAX_CANDIDATES);
+    }
+
+    #[test]
+    fn should_truncate_at_maximum_hypotheses() {
+        let slice: &[&[bool]] = &[&[true], &[true], &[true]];
+        let result = hypotheses(slice, 0, 4).unwrap();
+        assert_eq!(result.tables.len(), 4);
+        assert!(result.truncated);
+    }
+
+    #[test]
+    fn should_skip_tests_without_anything_to_learn() {
+        let slice: &[&[bool]] = &[&[true, false], &[false, true]];
+        assert_eq!(hypotheses(slice, 0, 100), Err(Skipped::Independent));
+        let slice: &[&[bool]] = &[&[false], &[true]];
+        assert_eq!(hypotheses(slice, 0, 100), Err(Skipped::NeverPassed));
+    }
+
+    #[test]
+    fn should_convert_hypothesis_to_expression() {
+        // Passes when candidate 0 passes, whatever candidate 1 does
+        let table = |bits: &[bool]| BitVector::from_bools(bits);
+        assert_eq!(hypothesis_expression(&table(&[false, true, false, true]), &[4, 7]), Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(4)),
+            })),
+        }));
+        assert_eq!(hypothesis_expression(&table(&[true; 4]), &[4, 7]), None);
+    }
+
+    #[test]
+    fn should_pick_experiments_that_split_hypotheses() {
+        let slice: &[&[bool]] = &[&[true, false], &[true, true], &[true, false]];
+        let result = hypotheses(slice, 0, 100).unwrap();
+        let experiments = plan_experiments(slice::from_ref(&result), 3);
+
+        // Breaking one candidate separates "needs it" from "doesn't", which no other single experiment beats
+        assert_eq!(experiments[0].force_fail.len(), 1);
+        assert!(experiments.windows(2).all(|pair| pair[1].expected_remaining < pair[0].expected_remaining));
+        assert!(experiments.last().unwrap().expected_remaining >= 1.0);
+    }
+}
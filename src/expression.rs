@@ -2,10 +2,11 @@ extern crate rustc_serialize;
 use rustc_serialize::json::{ToJson, Json};
 use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Operator {
     Or,
     And,
+    AtLeast(usize), // At least this many of the operands
 }
 
 impl ToJson for Operator {
@@ -13,28 +14,56 @@ impl ToJson for Operator {
         match *self {
             Operator::Or => Json::String("Or".to_owned()),
             Operator::And => Json::String("And".to_owned()),
+            Operator::AtLeast(_) => Json::String("AtLeast".to_owned()),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Operand {
     Test(i32),
+    Not(i32), // The test has to fail
     Expression(Expression),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A test that has to pass, or with `negated`, has to fail
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Literal {
+    pub test: i32,
+    pub negated: bool,
+}
+
+impl Literal {
+    pub fn operand(&self) -> Operand {
+        if self.negated {
+            Operand::Not(self.test)
+        } else {
+            Operand::Test(self.test)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Expression {
     pub operator: Operator,
     pub operands: Vec<Operand>,
 }
 
-pub fn expression_json(expression: &Expression, lookup: &Vec<String>) -> Json {
+pub fn expression_json(expression: &Expression, lookup: &[String]) -> Json {
     let mut map = BTreeMap::new();
     map.insert("operator".to_owned(), expression.operator.to_json());
+    if let Operator::AtLeast(threshold) = expression.operator {
+        map.insert("threshold".to_owned(), threshold.to_json());
+    }
     let operands: Vec<Json> = expression.operands.iter()
         .map(|operand| match operand {
             &Operand::Test(id) => Json::String(lookup[id as usize].clone()),
+            &Operand::Not(id) => {
+                let mut not = BTreeMap::new();
+                not.insert("operator".to_owned(), "Not".to_json());
+                not.insert("inputs".to_owned(), vec!(lookup[id as usize].clone()).to_json());
+                Json::Object(not)
+            },
             &Operand::Expression(ref e) => expression_json(e, lookup),
         })
         .collect();
@@ -42,19 +71,132 @@ pub fn expression_json(expression: &Expression, lookup: &Vec<String>) -> Json {
     Json::Object(map)
 }
 
+/// Reads back what `expression_json` writes. Operator names can be in any case, and a lone test name is taken as an
+/// OR of just that test
+pub fn json_expression(json: &Json, lookup: &[String]) -> Result<Expression, String> {
+    match json_operand(json, lookup)? {
+        Operand::Expression(expression) => Ok(expression),
+        test => Ok(Expression { operator: Operator::Or, operands: vec!(test) }),
+    }
+}
+
+fn json_operand(json: &Json, lookup: &[String]) -> Result<Operand, String> {
+    let test = |name: &str| lookup.iter()
+        .position(|test| test == name)
+        .map(|id| id as i32)
+        .ok_or_else(|| format!("Unknown test \"{}\"", name));
+    if let Some(name) = json.as_string() {
+        return Ok(Operand::Test(test(name)?));
+    }
+
+    let operator = json.find("operator").and_then(Json::as_string).map(str::to_lowercase);
+    let inputs = json.find("inputs").and_then(Json::as_array);
+    let (operator, inputs) = match (operator, inputs) {
+        (Some(operator), Some(inputs)) if !inputs.is_empty() => (operator, inputs),
+        _ => return Err(format!("Expected a test name, or an operator with inputs, found {}", json)),
+    };
+    let operator = match &operator[..] {
+        "or" => Operator::Or,
+        "and" => Operator::And,
+        "atleast" => match json.find("threshold").and_then(Json::as_u64) {
+            Some(threshold) => Operator::AtLeast(threshold as usize),
+            None => return Err(format!("Expected a threshold in {}", json)),
+        },
+        "not" => return match inputs[..] {
+            [Json::String(ref name)] => Ok(Operand::Not(test(name)?)),
+            _ => Err(format!("Only a single test can be negated, found {}", json)),
+        },
+        _ => return Err(format!("Unknown operator \"{}\"", operator)),
+    };
+    Ok(Operand::Expression(Expression {
+        operator,
+        operands: inputs.iter().map(|input| json_operand(input, lookup)).collect::<Result<Vec<Operand>, String>>()?,
+    }))
+}
+
+/// The expression written out the way people would, e.g. `A & (B | !C)` or `2 of (A, B, C)`. Names that could be
+/// mistaken for part of the syntax are quoted
+pub fn expression_infix(expression: &Expression, lookup: &[String]) -> String {
+    if let [ref operand] = expression.operands[..] {
+        if let Operator::Or | Operator::And = expression.operator {
+            return operand_infix(operand, lookup);
+        }
+    }
+
+    let operands: Vec<String> = expression.operands.iter()
+        .map(|operand| match *operand {
+            Operand::Expression(ref inner) if inner.operands.len() > 1 => match inner.operator {
+                Operator::AtLeast(_) => expression_infix(inner, lookup),
+                _ => format!("({})", expression_infix(inner, lookup)),
+            },
+            ref operand => operand_infix(operand, lookup),
+        })
+        .collect();
+    match expression.operator {
+        Operator::Or => operands.join(" | "),
+        Operator::And => operands.join(" & "),
+        Operator::AtLeast(threshold) => format!("{} of ({})", threshold, operands.join(", ")),
+    }
+}
+
+fn operand_infix(operand: &Operand, lookup: &[String]) -> String {
+    match *operand {
+        Operand::Test(id) => name_infix(&lookup[id as usize]),
+        Operand::Not(id) => format!("!{}", name_infix(&lookup[id as usize])),
+        Operand::Expression(ref inner) => expression_infix(inner, lookup),
+    }
+}
+
+fn name_infix(name: &str) -> String {
+    let plain = !name.is_empty()
+        && name != "of"
+        && !name.contains("->")
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '@');
+    if plain {
+        return name.to_owned();
+    }
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 impl Expression {
     pub fn evaluate(&self, results: &Vec<bool>, variable_to_index: &Vec<usize>) -> bool {
         match self.operator {
             Operator::Or => self.operands.iter().any(|operand| evaluate_operand(operand, results, variable_to_index)),
             Operator::And => self.operands.iter().all(|operand| evaluate_operand(operand, results, variable_to_index)),
+            Operator::AtLeast(threshold) => self.operands.iter()
+                .filter(|operand| evaluate_operand(operand, results, variable_to_index))
+                .count() >= threshold,
         }
     }
 
+    /// Like `evaluate`, with bit `i` of the mask standing in for `results[i]`
+    pub fn evaluate_mask(&self, results: u64, variable_to_index: &[usize]) -> bool {
+        match self.operator {
+            Operator::Or => self.operands.iter()
+                .any(|operand| evaluate_operand_mask(operand, results, variable_to_index)),
+            Operator::And => self.operands.iter()
+                .all(|operand| evaluate_operand_mask(operand, results, variable_to_index)),
+            Operator::AtLeast(threshold) => self.operands.iter()
+                .filter(|operand| evaluate_operand_mask(operand, results, variable_to_index))
+                .count() >= threshold,
+        }
+    }
+
+    /// Whether any test has to fail
+    pub fn has_negation(&self) -> bool {
+        self.operands.iter().any(|operand| match *operand {
+            Operand::Test(_) => false,
+            Operand::Not(_) => true,
+            Operand::Expression(ref e) => e.has_negation(),
+        })
+    }
+
     pub fn variables(&self) -> Vec<i32> {
         let mut vars = Vec::<i32>::new();
         for operand in &self.operands {
             match operand {
-                &Operand::Test(id) => {
+                &Operand::Test(id) | &Operand::Not(id) => {
                     if !vars.contains(&id) {
                         vars.push(id);
                     }
@@ -72,13 +214,39 @@ impl Expression {
     }
 }
 
+/// The expression that's true exactly when this one is false. `k of n` fails when `n - k + 1` of its operands do
+pub fn negated(expression: &Expression) -> Expression {
+    let operator = match expression.operator {
+        Operator::Or => Operator::And,
+        Operator::And => Operator::Or,
+        Operator::AtLeast(threshold) => Operator::AtLeast((expression.operands.len() + 1).saturating_sub(threshold)),
+    };
+    let operands = expression.operands.iter()
+        .map(|operand| match *operand {
+            Operand::Test(id) => Operand::Not(id),
+            Operand::Not(id) => Operand::Test(id),
+            Operand::Expression(ref inner) => Operand::Expression(negated(inner)),
+        })
+        .collect();
+    Expression { operator, operands }
+}
+
 fn evaluate_operand(operand: &Operand, results: &Vec<bool>, variable_to_index: &Vec<usize>) -> bool {
     match *operand {
         Operand::Test(id) => results[variable_to_index[id as usize]],
+        Operand::Not(id) => !results[variable_to_index[id as usize]],
         Operand::Expression(ref expression) => expression.evaluate(results, variable_to_index),
     }
 }
 
+fn evaluate_operand_mask(operand: &Operand, results: u64, variable_to_index: &[usize]) -> bool {
+    match *operand {
+        Operand::Test(id) => (results >> variable_to_index[id as usize]) & 1 == 1,
+        Operand::Not(id) => (results >> variable_to_index[id as usize]) & 1 == 0,
+        Operand::Expression(ref expression) => expression.evaluate_mask(results, variable_to_index),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,6 +303,107 @@ mod tests {
         assert_eq!(evaluate(&expression, &vec!(true, true, true)), true);
     }
 
+    #[test]
+    fn should_evaluate_not() {
+        let expression = Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(0), Operand::Not(1))
+        };
+        assert!(evaluate(&expression, &vec!(true, false)));
+        assert!(!evaluate(&expression, &vec!(true, true)));
+        assert!(expression.evaluate_mask(0b01, &[0, 1]));
+        assert!(!expression.evaluate_mask(0b11, &[0, 1]));
+        assert!(expression.has_negation());
+
+        let names = vec!("A".to_owned(), "B".to_owned());
+        assert_eq!(expression_json(&expression, &names).to_string(),
+                   r#"{"inputs":["A",{"inputs":["B"],"operator":"Not"}],"operator":"And"}"#);
+    }
+
+    #[test]
+    fn should_evaluate_at_least() {
+        let expression = Expression {
+            operator: Operator::AtLeast(2),
+            operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Not(2))
+        };
+        assert!(!evaluate(&expression, &vec!(false, false, true)));
+        assert!(!evaluate(&expression, &vec!(true, false, true)));
+        assert!(evaluate(&expression, &vec!(true, false, false)));
+        assert!(evaluate(&expression, &vec!(true, true, true)));
+        assert!(expression.evaluate_mask(0b011, &[0, 1, 2]));
+        assert!(!expression.evaluate_mask(0b110, &[0, 1, 2]));
+    }
+
+    #[test]
+    fn should_negate() {
+        // !(A & (B | 2 of (A, B, !C)))
+        let expression = Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(0), Operand::Expression(Expression {
+                operator: Operator::Or,
+                operands: vec!(Operand::Test(1), Operand::Expression(Expression {
+                    operator: Operator::AtLeast(2),
+                    operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Not(2))
+                }))
+            }))
+        };
+        let negation = negated(&expression);
+        for results in 0..8u64 {
+            assert_ne!(expression.evaluate_mask(results, &[0, 1, 2]), negation.evaluate_mask(results, &[0, 1, 2]));
+        }
+    }
+
+    #[test]
+    fn should_write_infix() {
+        let names: Vec<String> = vec!("A", "B", "Manual Starter", "2", "D").into_iter().map(String::from).collect();
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Not(0), Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(1), Operand::Expression(Expression {
+                    operator: Operator::AtLeast(2),
+                    operands: vec!(Operand::Test(2), Operand::Test(3), Operand::Test(4))
+                }))
+            }))
+        };
+        assert_eq!(expression_infix(&expression, &names), r#"!A | (B & 2 of ("Manual Starter", "2", D))"#);
+        let lone = Expression { operator: Operator::Or, operands: vec!(Operand::Test(4)) };
+        assert_eq!(expression_infix(&lone, &names), "D");
+    }
+
+    #[test]
+    fn should_read_json_back() {
+        let names: Vec<String> = vec!("A", "B", "C").into_iter().map(String::from).collect();
+        let expression = Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Not(0), Operand::Expression(Expression {
+                operator: Operator::AtLeast(2),
+                operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Test(2))
+            }))
+        };
+        assert_eq!(json_expression(&expression_json(&expression, &names), &names), Ok(expression));
+        assert_eq!(json_expression(&"B".to_json(), &names),
+                   Ok(Expression { operator: Operator::Or, operands: vec!(Operand::Test(1)) }));
+        let lowercase = Json::from_str(r#"{"operator": "or", "inputs": ["A", "D"]}"#).unwrap();
+        assert_eq!(json_expression(&lowercase, &names), Err("Unknown test \"D\"".to_owned()));
+    }
+
+    #[test]
+    fn should_evaluate_mask_like_vector() {
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Test(2), Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(0), Operand::Test(1))
+            }))
+        };
+        let mapping = vec!(0, 1, 2);
+        for mask in 0..8u64 {
+            let results: Vec<bool> = (0..3).map(|i| (mask >> i) & 1 == 1).collect();
+            assert_eq!(expression.evaluate_mask(mask, &mapping), evaluate(&expression, &results));
+        }
+    }
+
     #[test]
     fn should_calculate_unique_variable_count_when_one_operator() {
         let expression = Expression {
@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::slice;
+use expression::*;
+use reducer::sum_of_products;
+
+/// Rewrites an OR of ANDs into an equivalent nested expression, by pulling out the tests that several terms share:
+/// `(A & B & C) | (A & B & D)` becomes `A & B & (C | D)`
+pub fn factor(expression: &Expression) -> Expression {
+    let all_terms = sum_of_products(expression);
+    let mut terms: Vec<BTreeSet<Literal>> = all_terms.iter()
+        .filter(|term| !all_terms.iter().any(|other| other != *term && other.is_subset(term))) // Absorbed
+        .cloned()
+        .collect();
+    terms.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b))); // Same order as the reducers give
+    match factor_terms(&terms) {
+        Operand::Expression(expression) => expression,
+        test => Expression { operator: Operator::Or, operands: vec!(test) }, // A lone test is still an operator
+    }
+}
+
+fn factor_terms(terms: &[BTreeSet<Literal>]) -> Operand {
+    if terms.len() == 1 {
+        return joined(Operator::And, terms[0].iter().map(Literal::operand).collect());
+    }
+
+    let common: BTreeSet<Literal> = terms.iter().skip(1)
+        .fold(terms[0].clone(), |common, term| common.intersection(term).cloned().collect());
+    if !common.is_empty() {
+        let rest: Vec<BTreeSet<Literal>> = terms.iter()
+            .map(|term| term.difference(&common).cloned().collect())
+            .collect();
+        let mut operands: Vec<Operand> = common.iter().map(Literal::operand).collect();
+        operands.push(factor_terms(&rest));
+        return joined(Operator::And, operands);
+    }
+
+    // Split off the terms sharing the most frequent literal. The lowest id wins ties, so the output is stable
+    let literals: BTreeSet<Literal> = terms.iter().flat_map(|term| term.iter().cloned()).collect();
+    let count = |literal: Literal| terms.iter().filter(|term| term.contains(&literal)).count();
+    match literals.into_iter().max_by_key(|&literal| (count(literal), Reverse(literal))) {
+        Some(literal) if count(literal) > 1 => {
+            let (with, without): (Vec<BTreeSet<Literal>>, Vec<BTreeSet<Literal>>) = terms.iter()
+                .cloned()
+                .partition(|term| term.contains(&literal));
+            let mut operands = vec!(factor_terms(&with));
+            if !without.is_empty() {
+                operands.push(factor_terms(&without));
+            }
+            joined(Operator::Or, operands)
+        },
+        _ => joined(Operator::Or, terms.iter().map(|term| factor_terms(slice::from_ref(term))).collect()),
+    }
+}
+
+/// Combines operands, merging in any that already use the same operator. A single operand is left as it is
+fn joined(operator: Operator, operands: Vec<Operand>) -> Operand {
+    let mut flattened = Vec::with_capacity(operands.len());
+    for operand in operands {
+        match operand {
+            Operand::Expression(inner) => if inner.operator == operator {
+                flattened.extend(inner.operands);
+            } else {
+                flattened.push(Operand::Expression(inner));
+            },
+            test => flattened.push(test),
+        }
+    }
+
+    if flattened.len() == 1 {
+        return flattened.remove(0);
+    }
+    Operand::Expression(Expression { operator, operands: flattened })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn and(ids: &[i32]) -> Operand {
+        Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        })
+    }
+
+    fn or(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::Or, operands }
+    }
+
+    fn assert_equivalent(a: &Expression, b: &Expression) {
+        let mut variables = a.variables();
+        variables.extend(b.variables());
+        let variable_count = (*variables.iter().max().unwrap() + 1) as usize;
+        let variable_to_index: Vec<usize> = (0..variable_count).collect();
+        for results in 0..(1u64 << variable_count) {
+            assert_eq!(a.evaluate_mask(results, &variable_to_index), b.evaluate_mask(results, &variable_to_index),
+                       "{:?} and {:?} differ at {:b}", a, b, results);
+        }
+    }
+
+    #[test]
+    fn should_pull_out_shared_tests() {
+        let expression = or(vec!(and(&[0, 1, 2]), and(&[0, 1, 3])));
+        let factored = factor(&expression);
+        assert_eq!(factored, Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Expression(or(vec!(
+                Operand::Test(2),
+                Operand::Test(3),
+            )))),
+        });
+        assert_equivalent(&factored, &expression);
+    }
+
+    #[test]
+    fn should_nest_several_levels_deep() {
+        // (A & B) | (A & C) | (D & E) | (D & F) | G
+        let expression = or(vec!(and(&[0, 1]), and(&[0, 2]), and(&[3, 4]), and(&[3, 5]), Operand::Test(6)));
+        let factored = factor(&expression);
+        assert_eq!(factored, or(vec!(
+            Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(0), Operand::Expression(or(vec!(Operand::Test(1), Operand::Test(2))))),
+            }),
+            Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(3), Operand::Expression(or(vec!(Operand::Test(4), Operand::Test(5))))),
+            }),
+            Operand::Test(6),
+        )));
+        assert_equivalent(&factored, &expression);
+    }
+
+    #[test]
+    fn should_stay_equivalent_to_the_two_level_form() {
+        let expressions = vec!(
+            or(vec!(Operand::Test(0))),
+            or(vec!(and(&[0, 1, 2]))),
+            or(vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2]))),
+            or(vec!(and(&[1, 2]), and(&[2, 3]), Operand::Test(3), and(&[1, 4]), and(&[4, 1, 5]))),
+            or(vec!(and(&[0, 1, 2, 3]), and(&[0, 1, 4]), and(&[0, 5]), and(&[2, 3, 6]), Operand::Test(7))),
+        );
+
+        for expression in &expressions {
+            let factored = factor(expression);
+            assert_equivalent(&factored, expression);
+            assert_eq!(factor(&factored), factored);
+        }
+    }
+}
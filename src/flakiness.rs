@@ -0,0 +1,169 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use bitset::BitVector;
+use execution_matrix::ExecutionMatrix;
+
+/// All executions of a target that saw exactly the same set of other tests passing
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContextGroup {
+    pub context: Vec<usize>, // ids of the other tests that passed
+    pub passed: Vec<usize>, // execution indexes where the target passed
+    pub failed: Vec<usize>, // execution indexes where the target failed
+}
+
+impl ContextGroup {
+    /// A target passing and failing in the same context can't be explained by any dependency
+    pub fn is_contradictory(&self) -> bool {
+        !self.passed.is_empty() && !self.failed.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Flakiness {
+    pub test: usize,
+    pub inconsistency: f64, // fraction of executions that disagree with the majority of their group
+    pub contradictions: Vec<ContextGroup>,
+}
+
+/// Whether the target was skipped in the execution, given the tests skipped in each execution
+fn was_skipped(skipped: &[Vec<usize>], execution_index: usize, target_id: usize) -> bool {
+    skipped.get(execution_index).is_some_and(|tests| tests.contains(&target_id))
+}
+
+/// The target's executions grouped by context, leaving out those where it was skipped, since it never ran
+pub fn context_groups(matrix: &ExecutionMatrix, target_id: usize, skipped: &[Vec<usize>]) -> Vec<ContextGroup> {
+    let mut groups = BTreeMap::<BitVector, ContextGroup>::new();
+
+    for execution_index in (0..matrix.execution_count).filter(|&index| !was_skipped(skipped, index, target_id)) {
+        let passing = matrix.execution(execution_index);
+        let mut context = passing.clone();
+        context.set(target_id, false);
+
+        let group = groups.entry(context).or_insert_with_key(|context| ContextGroup {
+            context: context.ones().collect(),
+            passed: Vec::new(),
+            failed: Vec::new(),
+        });
+
+        if passing.get(target_id) {
+            group.passed.push(execution_index);
+        } else {
+            group.failed.push(execution_index);
+        }
+    }
+
+    groups.into_values().collect()
+}
+
+pub fn flakiness(matrix: &ExecutionMatrix, target_id: usize, skipped: &[Vec<usize>]) -> Flakiness {
+    let groups = context_groups(matrix, target_id, skipped);
+    let executions: usize = groups.iter().map(|group| group.passed.len() + group.failed.len()).sum();
+    let contradictions: Vec<ContextGroup> = groups.into_iter()
+        .filter(|group| group.is_contradictory())
+        .collect();
+
+    let minority: usize = contradictions.iter()
+        .map(|group| group.passed.len().min(group.failed.len()))
+        .sum();
+
+    Flakiness {
+        test: target_id,
+        inconsistency: if executions == 0 { 0.0 } else { minority as f64 / executions as f64 },
+        contradictions,
+    }
+}
+
+/// Every test with at least one contradiction, most inconsistent first. `skipped` lists the tests that were skipped
+/// in each execution, which don't count as failures
+pub fn rank_flaky(matrix: &ExecutionMatrix, skipped: &[Vec<usize>]) -> Vec<Flakiness> {
+    let mut ranked: Vec<Flakiness> = (0..matrix.test_count)
+        .map(|target_id| flakiness(matrix, target_id, skipped))
+        .filter(|flakiness| !flakiness.contradictions.is_empty())
+        .collect();
+
+    ranked.sort_by(|a, b| b.inconsistency.partial_cmp(&a.inconsistency).unwrap()
+        .then(b.contradictions.len().cmp(&a.contradictions.len()))
+        .then(a.test.cmp(&b.test)));
+    ranked
+}
+
+/// Tests that are inconsistent enough that they shouldn't be used as evidence for inference
+pub fn flaky_tests(ranked: &[Flakiness], threshold: f64) -> Vec<usize> {
+    let mut tests: Vec<usize> = ranked.iter()
+        .filter(|flakiness| flakiness.inconsistency > threshold)
+        .map(|flakiness| flakiness.test)
+        .collect();
+    tests.sort();
+    tests
+}
+
+pub fn flakiness_json(ranked: &[Flakiness], lookup: &[String]) -> Json {
+    let names = |ids: &[usize]| -> Json {
+        ids.iter().map(|&id| lookup[id].clone()).collect::<Vec<String>>().to_json()
+    };
+
+    ranked.iter()
+        .map(|flakiness| {
+            let contradictions: Vec<Json> = flakiness.contradictions.iter()
+                .map(|group| {
+                    let mut map = BTreeMap::new();
+                    map.insert("others_passing".to_owned(), names(&group.context));
+                    map.insert("passed".to_owned(), group.passed.to_json());
+                    map.insert("failed".to_owned(), group.failed.to_json());
+                    Json::Object(map)
+                })
+                .collect();
+
+            let mut map = BTreeMap::new();
+            map.insert("test".to_owned(), lookup[flakiness.test].to_json());
+            map.insert("inconsistency".to_owned(), flakiness.inconsistency.to_json());
+            map.insert("contradictions".to_owned(), contradictions.to_json());
+            Json::Object(map)
+        })
+        .collect::<Vec<Json>>()
+        .to_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_group_executions_by_other_passing_tests() {
+        let slice: &[&[bool]] = &[&[true, false, true], &[true, true, false]];
+        assert_eq!(context_groups(&ExecutionMatrix::new(slice), 0, &[]), vec!(
+            ContextGroup { context: vec!(), passed: vec!(2), failed: vec!() },
+            ContextGroup { context: vec!(1), passed: vec!(0), failed: vec!(1) },
+        ));
+    }
+
+    #[test]
+    fn should_measure_inconsistency_from_minority_outcomes() {
+        let slice: &[&[bool]] = &[
+            &[true, true, true, false],
+            &[true, true, true, true],
+        ];
+        let matrix = ExecutionMatrix::new(slice);
+        let result = flakiness(&matrix, 0, &[]);
+        assert_eq!(result.contradictions.len(), 1);
+        assert_eq!(result.inconsistency, 0.25);
+        assert!(flakiness(&matrix, 1, &[]).contradictions.is_empty());
+
+        // Skipped rather than failed in the last execution
+        let skipped = vec!(vec!(), vec!(), vec!(), vec!(0));
+        assert!(flakiness(&matrix, 0, &skipped).contradictions.is_empty());
+    }
+
+    #[test]
+    fn should_rank_most_inconsistent_first() {
+        let slice: &[&[bool]] = &[
+            &[true, false, true, true],
+            &[true, true, true, false],
+            &[true, true, true, true],
+        ];
+        let ranked = rank_flaky(&ExecutionMatrix::new(slice), &[]);
+        assert_eq!(ranked.iter().map(|flakiness| flakiness.test).collect::<Vec<usize>>(), vec!(0, 1));
+        assert_eq!(flaky_tests(&ranked, 0.0), vec!(0, 1));
+        assert!(flaky_tests(&ranked, 0.5).is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use expression::*;
+
+/// Dependencies with their common sub-expressions pulled out into groups. A group is referred to as if it were one
+/// more test: `Operand::Test(test_count + i)` stands for `groups[i]`
+#[derive(Debug, PartialEq)]
+pub struct Grouped {
+    pub expressions: Vec<Option<Expression>>,
+    pub groups: Vec<Expression>,
+}
+
+/// `@group1`, `@group2`, ...
+pub fn group_name(index: usize) -> String {
+    format!("@group{}", index + 1)
+}
+
+/// Repeatedly pulls out the largest sub-expression that appears more than once, across every test and every group
+/// found so far, until nothing is repeated. Operands are compared in any order, so `A | B` and `B | A` are the same
+/// group, which is written the way it first appeared
+pub fn extract_groups(expressions: &[Option<Expression>], test_count: usize) -> Grouped {
+    let mut expressions = expressions.to_vec();
+    let mut groups = Vec::<Expression>::new();
+
+    loop {
+        let repeated = {
+            let mut counts = BTreeMap::<Expression, (usize, &Expression)>::new();
+            for expression in expressions.iter().flat_map(|expression| expression.iter()).chain(groups.iter()) {
+                count_subexpressions(expression, &mut counts);
+            }
+            counts.into_iter()
+                .filter(|&(_, (count, _))| count > 1)
+                .min_by(|(a, _), (b, _)| size(b).cmp(&size(a)).then(a.cmp(b)))
+                .map(|(key, (_, first))| (key, first.clone()))
+        };
+
+        match repeated {
+            None => break,
+            Some((key, repeated)) => {
+                let reference = Operand::Test((test_count + groups.len()) as i32);
+                for expression in expressions.iter_mut().flat_map(|expression| expression.iter_mut()) {
+                    *expression = replaced(expression, &key, &reference);
+                }
+                for group in groups.iter_mut() {
+                    *group = replaced(group, &key, &reference);
+                }
+                groups.push(repeated);
+            },
+        }
+    }
+
+    Grouped { expressions, groups }
+}
+
+/// Puts the groups that an expression refers to back in place
+pub fn expand(expression: &Expression, groups: &[Expression], test_count: usize) -> Expression {
+    if let [Operand::Test(id)] = expression.operands[..] {
+        if id as usize >= test_count {
+            return expand(&groups[id as usize - test_count], groups, test_count);
+        }
+    }
+
+    Expression {
+        operator: expression.operator.clone(),
+        operands: expression.operands.iter()
+            .map(|operand| match *operand {
+                Operand::Test(id) if id as usize >= test_count => {
+                    Operand::Expression(expand(&groups[id as usize - test_count], groups, test_count))
+                },
+                Operand::Expression(ref inner) => Operand::Expression(expand(inner, groups, test_count)),
+                ref test => test.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// The expression with every list of operands sorted, so that the same block in a different order compares equal.
+/// Unlike `algebra::canonical`, nothing is flattened, so a block nested inside a larger one of the same kind is still
+/// there to be found
+fn unordered(expression: &Expression) -> Expression {
+    let mut operands: Vec<Operand> = expression.operands.iter()
+        .map(|operand| match *operand {
+            Operand::Expression(ref inner) => Operand::Expression(unordered(inner)),
+            ref test => test.clone(),
+        })
+        .collect();
+    operands.sort();
+    Expression { operator: expression.operator.clone(), operands }
+}
+
+/// Only sub-expressions with more than one operand are worth naming. Each is counted by its `unordered` form, along
+/// with where it first appeared
+fn count_subexpressions<'a>(expression: &'a Expression, counts: &mut BTreeMap<Expression, (usize, &'a Expression)>) {
+    if expression.operands.len() > 1 {
+        counts.entry(unordered(expression)).or_insert((0, expression)).0 += 1;
+    }
+    for operand in &expression.operands {
+        if let Operand::Expression(ref inner) = *operand {
+            count_subexpressions(inner, counts);
+        }
+    }
+}
+
+/// How many tests (and groups) an expression mentions, counting repeats
+fn size(expression: &Expression) -> usize {
+    expression.operands.iter()
+        .map(|operand| match *operand {
+            Operand::Test(_) | Operand::Not(_) => 1,
+            Operand::Expression(ref inner) => size(inner),
+        })
+        .sum()
+}
+
+/// Replaces every sub-expression whose `unordered` form is `target`
+fn replaced(expression: &Expression, target: &Expression, reference: &Operand) -> Expression {
+    if unordered(expression) == *target {
+        return Expression { operator: Operator::Or, operands: vec!(reference.clone()) }; // A lone input is an OR
+    }
+
+    Expression {
+        operator: expression.operator.clone(),
+        operands: expression.operands.iter()
+            .map(|operand| match *operand {
+                Operand::Expression(ref inner) if unordered(inner) == *target => reference.clone(),
+                Operand::Expression(ref inner) => Operand::Expression(replaced(inner, target, reference)),
+                ref test => test.clone(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tests(ids: &[i32]) -> Vec<Operand> {
+        ids.iter().map(|&id| Operand::Test(id)).collect()
+    }
+
+    fn expression(operator: Operator, operands: Vec<Operand>) -> Expression {
+        Expression { operator, operands }
+    }
+
+    // (Electric Starter | Manual Starter) & Pistons, with the tests numbered 0, 1 and 2
+    fn starter_ok() -> Expression {
+        let mut operands = vec!(Operand::Expression(expression(Operator::Or, tests(&[0, 1]))));
+        operands.extend(tests(&[2]));
+        expression(Operator::And, operands)
+    }
+
+    #[test]
+    fn should_name_blocks_shared_by_several_tests() {
+        let expressions = vec!(
+            None,
+            None,
+            None,
+            Some(starter_ok()),
+            Some(expression(Operator::Or, vec!(Operand::Expression(starter_ok()), Operand::Test(3)))),
+            Some(expression(Operator::And, vec!(Operand::Expression(starter_ok()), Operand::Test(4)))),
+        );
+        let grouped = extract_groups(&expressions, 6);
+
+        assert_eq!(grouped.groups, vec!(starter_ok()));
+        assert_eq!(grouped.expressions[3], Some(expression(Operator::Or, tests(&[6]))));
+        assert_eq!(grouped.expressions[4], Some(expression(Operator::Or, tests(&[6, 3]))));
+        assert_eq!(group_name(0), "@group1");
+
+        for (original, grouped_expression) in expressions.iter().zip(grouped.expressions.iter()) {
+            assert_eq!(grouped_expression.as_ref().map(|e| expand(e, &grouped.groups, 6)), *original);
+        }
+    }
+
+    #[test]
+    fn should_nest_smaller_groups_inside_larger_ones() {
+        let starter = expression(Operator::Or, tests(&[0, 1]));
+        let expressions = vec!(
+            Some(starter_ok()),
+            Some(starter_ok()),
+            Some(expression(Operator::And, vec!(Operand::Expression(starter.clone()), Operand::Test(5)))),
+        );
+        let grouped = extract_groups(&expressions, 6);
+
+        assert_eq!(grouped.groups, vec!(
+            expression(Operator::And, vec!(Operand::Test(7), Operand::Test(2))),
+            starter,
+        ));
+        for (original, grouped_expression) in expressions.iter().zip(grouped.expressions.iter()) {
+            assert_eq!(grouped_expression.as_ref().map(|e| expand(e, &grouped.groups, 6)), *original);
+        }
+    }
+
+    #[test]
+    fn should_leave_unrepeated_expressions_alone() {
+        let expressions = vec!(Some(starter_ok()), Some(expression(Operator::Or, tests(&[0, 2]))));
+        let grouped = extract_groups(&expressions, 3);
+        assert!(grouped.groups.is_empty());
+        assert_eq!(grouped.expressions, expressions);
+    }
+
+    #[test]
+    fn should_find_the_same_block_in_any_order() {
+        let expressions = vec!(
+            Some(expression(Operator::And, vec!(Operand::Test(5), Operand::Expression(starter_ok())))),
+            Some(expression(Operator::And, vec!(
+                Operand::Test(2),
+                Operand::Expression(expression(Operator::Or, tests(&[1, 0]))),
+            ))),
+        );
+        let grouped = extract_groups(&expressions, 6);
+
+        assert_eq!(grouped.groups, vec!(starter_ok()));
+        assert_eq!(grouped.expressions, vec!(
+            Some(expression(Operator::And, tests(&[5, 6]))),
+            Some(expression(Operator::Or, tests(&[6]))),
+        ));
+    }
+}
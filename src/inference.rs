@@ -0,0 +1,453 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use algebra::equivalent;
+use budget::*;
+use constraints::*;
+use dependency_expression::*;
+use evidence::*;
+use execution_matrix::*;
+use expression::*;
+use factor::*;
+use reducer::*;
+use threshold::*;
+
+pub struct InferenceOptions {
+    pub thresholds: EvidenceThresholds,
+    pub alternatives: bool, // Keep every minimum-cost expression, not just the first
+    pub factor: bool, // Nest the reduced expressions instead of leaving them as an OR of ANDs
+    pub negative: bool, // Allow dependencies on other tests failing
+    pub at_least: bool, // Write "any k of these n" as one threshold instead of every combination
+    pub reducer: Box<dyn Reducer + Sync>,
+    pub fallbacks: Vec<Box<dyn Reducer + Sync>>, // Tried in order when `reducer` runs out of budget
+    pub budget: BudgetLimits, // For each test, and again for each fallback
+    pub constraints: Vec<Constraint>, // What's already known about the dependencies
+}
+
+impl Default for InferenceOptions {
+    fn default() -> InferenceOptions {
+        InferenceOptions {
+            thresholds: EvidenceThresholds::default(),
+            alternatives: false,
+            factor: false,
+            negative: false,
+            at_least: false,
+            reducer: ReducerKind::Auto.build(&ReducerSettings::default()),
+            fallbacks: ReducerKind::Auto.fallbacks(&ReducerSettings::default()),
+            budget: BudgetLimits::default(),
+            constraints: Vec::new(),
+        }
+    }
+}
+
+/// Everything inferred about a single test
+#[derive(Debug, PartialEq)]
+pub struct TestModel {
+    pub status: Status,
+    pub evidence: Evidence,
+    pub expression: Option<Expression>, // Only set when the status is `DependsOn`
+    pub alternatives: Vec<Expression>, // Other expressions that are just as small as `expression`
+    pub gap: Option<usize>, // How far a heuristic reducer may be from the smallest expression
+    pub fallback: Option<Fallback>,
+    pub violations: Vec<Violation>, // Known constraints that the history disagrees with
+}
+
+/// Which reducers ran out of budget, and what was used instead
+#[derive(Debug, PartialEq)]
+pub struct Fallback {
+    pub exceeded: Vec<(&'static str, BudgetExceeded)>,
+    pub reducer: &'static str,
+}
+
+fn reduce_within(reducer: &dyn Reducer, expression: &Expression, alternatives: bool, budget: &Budget)
+                 -> Result<(Vec<Expression>, Option<usize>), BudgetExceeded> {
+    if alternatives {
+        Ok((reducer.reduce_all_within(expression, budget)?, None))
+    } else {
+        let (reduced, gap) = reducer.reduce_within(expression, budget)?;
+        Ok((reduced.into_iter().collect(), gap))
+    }
+}
+
+/// Tries the reducer, then each fallback with a fresh budget, and finally absorption, which always finishes quickly
+fn reduce_with_fallback(expression: &Expression, options: &InferenceOptions, budget: &Budget)
+                        -> (Vec<Expression>, Option<usize>, Option<Fallback>) {
+    let mut exceeded = Vec::new();
+    match reduce_within(options.reducer.as_ref(), expression, options.alternatives, budget) {
+        Ok((reduced, gap)) => return (reduced, gap, None),
+        Err(reason) => exceeded.push((options.reducer.name(), reason)),
+    }
+
+    for reducer in &options.fallbacks {
+        match reduce_within(reducer.as_ref(), expression, options.alternatives, &Budget::start(&options.budget)) {
+            Ok((reduced, gap)) => return (reduced, gap, Some(Fallback { exceeded, reducer: reducer.name() })),
+            Err(reason) => exceeded.push((reducer.name(), reason)),
+        }
+    }
+
+    let reduced = if options.alternatives {
+        Absorption.reduce_all(expression)
+    } else {
+        Absorption.reduce(expression).into_iter().collect()
+    };
+    (reduced, None, Some(Fallback { exceeded, reducer: Absorption.name() }))
+}
+
+pub fn infer(matrix: &ExecutionMatrix, target_id: usize, options: &InferenceOptions) -> TestModel {
+    let budget = Budget::start(&options.budget);
+    let all_contexts = matrix.distinct_contexts(target_id);
+    let violations = violations(&options.constraints, target_id, &all_contexts);
+    let set_aside = set_aside(&options.constraints, target_id);
+    let contexts = without_tests(&all_contexts, &set_aside);
+    let evidence = evidence(&contexts, matrix.test_count - set_aside.len());
+
+    if let Some(expression) = fixed(&options.constraints, target_id) {
+        return TestModel {
+            status: Status::DependsOn,
+            evidence,
+            expression: Some(expression.clone()),
+            alternatives: Vec::new(),
+            gap: None,
+            fallback: None,
+            violations,
+        };
+    }
+
+    let status = match status(&evidence, &options.thresholds) {
+        // Passing while everything else failed might be exactly what the test needs
+        Status::Independent if options.negative => Status::DependsOn,
+        status => status,
+    };
+    let test_dependency = match status {
+        Status::DependsOn if options.negative => {
+            let others: Vec<usize> = (0..matrix.test_count)
+                .filter(|other_id| *other_id != target_id && !set_aside.contains(other_id))
+                .collect();
+            negative_dependency_expression(&contexts, &others)
+        },
+        Status::DependsOn => contexts_dependency_expression(&contexts),
+        _ => None,
+    };
+    let (mut reduced, gap, fallback) = test_dependency
+        .map_or((Vec::new(), None, None), |test_dependency| reduce_with_fallback(&test_dependency, options, &budget));
+    let status = match status {
+        // Passing whatever the others did, which only negative mode can find out
+        Status::DependsOn if reduced.is_empty() => Status::Independent,
+        status => status,
+    };
+    if options.factor {
+        reduced = reduced.iter().map(factor).collect();
+    }
+    if options.at_least {
+        reduced = reduced.iter().map(thresholds).collect();
+    }
+
+    let required = required(&options.constraints, target_id);
+    let status = match status {
+        Status::Independent | Status::DependsOn if !required.is_empty() => {
+            reduced = if reduced.is_empty() {
+                vec!(with_required(&required, None))
+            } else {
+                reduced.iter().map(|expression| with_required(&required, Some(expression))).collect()
+            };
+            Status::DependsOn
+        },
+        status => status,
+    };
+    let expression = if reduced.is_empty() { None } else { Some(reduced.remove(0)) };
+
+    TestModel {
+        status,
+        evidence,
+        expression,
+        alternatives: reduced,
+        gap,
+        fallback,
+        violations,
+    }
+}
+
+/// Whether two models agree, however their expressions are written
+pub fn same_model(a: &TestModel, b: &TestModel) -> bool {
+    a.status == b.status && match (&a.expression, &b.expression) {
+        (Some(a), Some(b)) => equivalent(a, b),
+        (a, b) => a == b,
+    }
+}
+
+/// Infers every test, spread over `threads` workers. Results are in test order, exactly as if run one at a time
+pub fn infer_all(matrix: &ExecutionMatrix, options: &InferenceOptions, threads: usize) -> Vec<TestModel> {
+    infer_some(matrix, &(0..matrix.test_count).collect::<Vec<usize>>(), options, threads)
+}
+
+/// `infer_all`, for only some of the tests. Results are in the order of `targets`
+pub fn infer_some(matrix: &ExecutionMatrix, targets: &[usize], options: &InferenceOptions, threads: usize)
+                  -> Vec<TestModel> {
+    if threads <= 1 {
+        return targets.iter().map(|&target_id| infer(matrix, target_id, options)).collect();
+    }
+
+    let next_target = AtomicUsize::new(0);
+    let mut models: Vec<(usize, TestModel)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| {
+                let mut models = Vec::new();
+                loop {
+                    let index = next_target.fetch_add(1, Ordering::Relaxed);
+                    if index >= targets.len() {
+                        return models;
+                    }
+                    models.push((index, infer(matrix, targets[index], options)));
+                }
+            }))
+            .collect();
+
+        workers.into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    models.sort_by_key(|&(index, _)| index);
+    models.into_iter().map(|(_, model)| model).collect()
+}
+
+/// `{"reducer": "absorption", "exceeded": [{"reducer": "qm", "budget": "time"}, ...]}`
+fn fallback_json(fallback: &Fallback) -> Json {
+    let exceeded: Vec<Json> = fallback.exceeded.iter()
+        .map(|&(reducer, reason)| {
+            let mut map = BTreeMap::new();
+            map.insert("reducer".to_owned(), reducer.to_json());
+            map.insert("budget".to_owned(), reason.name().to_json());
+            Json::Object(map)
+        })
+        .collect();
+
+    let mut map = BTreeMap::new();
+    map.insert("reducer".to_owned(), fallback.reducer.to_json());
+    map.insert("exceeded".to_owned(), exceeded.to_json());
+    Json::Object(map)
+}
+
+/// `null` for independent tests, an operator for dependent tests, and a status for anything else
+pub fn model_json(model: &TestModel, lookup: &[String]) -> Json {
+    match (model.status, &model.expression) {
+        (Status::DependsOn, Some(expression)) => {
+            let mut json = expression_json(expression, lookup);
+            if let Json::Object(ref mut map) = json {
+                if !model.alternatives.is_empty() {
+                    map.insert("alternatives".to_owned(), model.alternatives.iter()
+                        .map(|alternative| expression_json(alternative, lookup))
+                        .collect::<Vec<Json>>()
+                        .to_json());
+                }
+                if let Some(gap) = model.gap {
+                    map.insert("gap".to_owned(), gap.to_json());
+                }
+                if let Some(ref fallback) = model.fallback {
+                    map.insert("fallback".to_owned(), fallback_json(fallback));
+                }
+            }
+            json
+        },
+        (Status::Independent, _) => Json::Null,
+        (status, _) => {
+            let mut map = BTreeMap::new();
+            map.insert("status".to_owned(), status.name().to_json());
+            map.insert("passing_executions".to_owned(), model.evidence.passing_executions.to_json());
+            map.insert("passing_contexts".to_owned(), model.evidence.passing_contexts.to_json());
+            Json::Object(map)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infer(slice: &[&[bool]], target_id: usize, options: &InferenceOptions) -> TestModel {
+        super::infer(&ExecutionMatrix::new(slice), target_id, options)
+    }
+
+    #[test]
+    fn should_only_build_expressions_for_dependent_tests() {
+        let slice: &[&[bool]] = &[&[true, true, false], &[true, false, false], &[false, false, false]];
+        let options = InferenceOptions::default();
+        assert_eq!(infer(slice, 0, &options).status, Status::Independent);
+        assert_eq!(infer(slice, 1, &options), TestModel {
+            status: Status::DependsOn,
+            evidence: Evidence {
+                passing_executions: 1,
+                passing_contexts: 1,
+                informative_contexts: 1,
+                independent: false,
+            },
+            expression: Some(Expression {
+                operator: Operator::Or,
+                operands: vec!(Operand::Test(0)),
+            }),
+            alternatives: vec!(),
+            gap: None,
+            fallback: None,
+            violations: vec!(),
+        });
+        assert_eq!(infer(slice, 2, &options).expression, None);
+    }
+
+    #[test]
+    fn should_describe_status_when_no_dependency_can_be_given() {
+        let slice: &[&[bool]] = &[&[false, false], &[true, true]];
+        let names = vec!("A".to_owned(), "B".to_owned());
+        let json = model_json(&infer(slice, 0, &InferenceOptions::default()), &names);
+        assert_eq!(json.to_string(), r#"{"passing_contexts":0,"passing_executions":0,"status":"never-passed"}"#);
+        assert_eq!(model_json(&infer(slice, 1, &InferenceOptions::default()), &names), Json::Null);
+    }
+
+    #[test]
+    fn should_fall_back_when_out_of_budget() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, false, true],
+            &[true, false, true, false, true],
+            &[true, false, false, true, true],
+            &[true, true, true, true, false],
+        ];
+        let options = InferenceOptions {
+            budget: BudgetLimits { time: None, steps: Some(1) },
+            ..InferenceOptions::default()
+        };
+        let model = infer(slice, 3, &options);
+        assert_eq!(model.expression, infer(slice, 3, &InferenceOptions::default()).expression);
+        assert_eq!(model.fallback, Some(Fallback {
+            exceeded: vec!(("auto", BudgetExceeded::Steps), ("espresso", BudgetExceeded::Steps)),
+            reducer: "absorption",
+        }));
+
+        let names: Vec<String> = vec!("A", "B", "C", "D").into_iter().map(String::from).collect();
+        let json = model_json(&model, &names);
+        assert_eq!(json.find_path(&["fallback", "reducer"]), Some(&"absorption".to_json()));
+    }
+
+    #[test]
+    fn should_factor_when_asked() {
+        let slice: &[&[bool]] = &[&[true, true], &[false, false], &[true, false], &[false, true], &[true, true]];
+        let expression = |operator, operands| Expression { operator, operands };
+        let options = InferenceOptions { factor: true, ..InferenceOptions::default() };
+        assert_eq!(infer(slice, 4, &InferenceOptions::default()).expression, Some(expression(Operator::Or, vec!(
+            Operand::Expression(expression(Operator::And, vec!(Operand::Test(0), Operand::Test(2)))),
+            Operand::Expression(expression(Operator::And, vec!(Operand::Test(0), Operand::Test(3)))),
+        ))));
+        assert_eq!(infer(slice, 4, &options).expression, Some(expression(Operator::And, vec!(
+            Operand::Test(0),
+            Operand::Expression(expression(Operator::Or, vec!(Operand::Test(2), Operand::Test(3)))),
+        ))));
+    }
+
+    #[test]
+    fn should_find_tests_that_need_others_to_fail_when_asked() {
+        // A passes only when B fails and C passes, and C passes when exactly one of A and B does
+        let slice: &[&[bool]] = &[
+            &[true, false, false, true],
+            &[false, true, false, false],
+            &[true, true, false, true],
+        ];
+        let options = InferenceOptions { negative: true, ..InferenceOptions::default() };
+        assert_eq!(infer(slice, 0, &InferenceOptions::default()).expression, Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Test(2)),
+        }));
+
+        let model = infer(slice, 0, &options);
+        assert_eq!(model.status, Status::DependsOn);
+        assert_eq!(model.expression, Some(Expression { operator: Operator::And, operands: vec!(
+            Operand::Not(1),
+            Operand::Test(2),
+        )}));
+        let names: Vec<String> = vec!("A", "B", "C").into_iter().map(String::from).collect();
+        assert_eq!(model_json(&model, &names).to_string(),
+                   r#"{"inputs":[{"inputs":["B"],"operator":"Not"},"C"],"operator":"And"}"#);
+        assert_eq!(infer(slice, 1, &options).status, Status::DependsOn);
+        let either: &[&[bool]] = &[&[true, true], &[true, false]];
+        assert_eq!(infer(either, 0, &options).status, Status::Independent);
+        let and = |operands| Operand::Expression(Expression { operator: Operator::And, operands });
+        assert_eq!(infer(slice, 2, &options).expression, Some(Expression { operator: Operator::Or, operands: vec!(
+            and(vec!(Operand::Test(0), Operand::Not(1))),
+            and(vec!(Operand::Not(0), Operand::Test(1))),
+        )}));
+    }
+
+    #[test]
+    fn should_write_thresholds_when_asked() {
+        // D passes whenever any two of A, B and C do
+        let slice: &[&[bool]] = &[
+            &[true, true, false, true, false, false],
+            &[true, false, true, false, true, false],
+            &[false, true, true, false, false, true],
+            &[true, true, true, false, false, false],
+        ];
+        let options = InferenceOptions { at_least: true, ..InferenceOptions::default() };
+        assert_eq!(infer(slice, 3, &InferenceOptions::default()).expression.unwrap().operands.len(), 3);
+
+        let model = infer(slice, 3, &options);
+        assert_eq!(model.expression, Some(Expression {
+            operator: Operator::AtLeast(2),
+            operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Test(2)),
+        }));
+        let names: Vec<String> = vec!("A", "B", "C", "D").into_iter().map(String::from).collect();
+        assert_eq!(model_json(&model, &names).to_string(),
+                   r#"{"inputs":["A","B","C"],"operator":"AtLeast","threshold":2}"#);
+    }
+
+    #[test]
+    fn should_honor_constraints() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, true],
+            &[true, true, false, false],
+            &[true, true, false, false],
+            &[false, true, true, false],
+        ];
+        let names: Vec<String> = vec!("Engine", "Gas Lines", "Paint", "Starter").into_iter()
+            .map(String::from)
+            .collect();
+        let constraints = "Engine -> \"Gas Lines\"\nPaint !-> Engine\nStarter = !Paint";
+        let options = InferenceOptions {
+            constraints: parse_constraints(constraints, &names).unwrap(),
+            ..InferenceOptions::default()
+        };
+        let or = |id| Some(Expression { operator: Operator::Or, operands: vec!(Operand::Test(id)) });
+
+        assert_eq!(infer(slice, 2, &InferenceOptions::default()).expression, Some(Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(0), Operand::Test(1)),
+        }));
+        let paint = infer(slice, 2, &options);
+        assert_eq!(paint.expression, or(1));
+        assert_eq!(paint.violations.iter().map(|violation| violation.executions).collect::<Vec<usize>>(), vec!(2));
+
+        assert_eq!(infer(slice, 0, &InferenceOptions::default()).status, Status::Independent);
+        let engine = infer(slice, 0, &options);
+        assert_eq!((engine.status, engine.expression), (Status::DependsOn, or(1)));
+        assert_eq!(engine.violations[0].executions, 1);
+
+        let starter = infer(slice, 3, &options);
+        assert_eq!(starter.expression, Some(Expression { operator: Operator::Or, operands: vec!(Operand::Not(2)) }));
+        assert_eq!(starter.violations[0].constraint.kind(), "fixed"); // It passed alongside Paint once
+    }
+
+    #[test]
+    fn should_infer_identically_in_parallel() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, true, true, false, true],
+            &[true, false, false, false, true, true, true],
+            &[true, true, true, false, true, false, false],
+            &[false, true, false, true, true, true, false],
+            &[false, false, false, false, true, false, true],
+        ];
+        let matrix = ExecutionMatrix::new(slice);
+        let options = InferenceOptions::default();
+        let sequential = infer_all(&matrix, &options, 1);
+        assert_eq!(sequential.len(), 5);
+        for threads in 2..6 {
+            assert_eq!(infer_all(&matrix, &options, threads), sequential);
+        }
+    }
+}
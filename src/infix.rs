@@ -0,0 +1,249 @@
+use expression::*;
+
+/// A piece of an infix expression or constraint, as written by `expression_infix`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Token {
+    Name(String),
+    Number(usize),
+    Or,
+    And,
+    Not,
+    Open,
+    Close,
+    Comma,
+    Of,
+    DependsOn, // ->
+    NeverDependsOn, // !->
+    Is, // =
+}
+
+/// Splits the text into tokens. Names are either quoted, with `\` escaping, or a run of letters, digits, `_`, `-`,
+/// `.` and `@` that doesn't start with a digit
+pub fn tokens(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let (next, after) = (chars.get(i + 1).cloned(), chars.get(i + 2).cloned());
+        i += 1;
+        match c {
+            c if c.is_whitespace() => {},
+            '|' => tokens.push(Token::Or),
+            '&' => tokens.push(Token::And),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Is),
+            '-' if next == Some('>') => {
+                i += 1;
+                tokens.push(Token::DependsOn);
+            },
+            '!' if next == Some('-') && after == Some('>') => {
+                i += 2;
+                tokens.push(Token::NeverDependsOn);
+            },
+            '!' => tokens.push(Token::Not),
+            '"' => {
+                let mut name = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err(format!("Unclosed quote in \"{}\"", text)),
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            name.push(chars[i + 1]);
+                            i += 1;
+                        },
+                        Some(&c) => name.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                tokens.push(Token::Name(name));
+            },
+            c if c.is_ascii_digit() => {
+                let start = i - 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let digits: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(digits.parse().map_err(|_| format!("{} is too large", digits))?));
+            },
+            c if is_name_char(c) => {
+                let start = i - 1;
+                // A name runs up to the next symbol, and `A->B` is two names
+                while i < chars.len() && is_name_char(chars[i])
+                    && !(chars[i] == '-' && chars.get(i + 1) == Some(&'>')) {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                tokens.push(if name == "of" { Token::Of } else { Token::Name(name) });
+            },
+            c => return Err(format!("Unexpected '{}' in \"{}\"", c, text)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '@'
+}
+
+/// Reads an expression written the way `expression_infix` writes them, such as `A & (B | !C)` or `2 of (A, B, C)`.
+/// `&` binds tighter than `|`
+pub fn parse_infix(text: &str, lookup: &[String]) -> Result<Expression, String> {
+    parse_tokens(&tokens(text)?, lookup)
+}
+
+/// `parse_infix`, for text that's already split into tokens
+pub fn parse_tokens(tokens: &[Token], lookup: &[String]) -> Result<Expression, String> {
+    let mut parser = Parser { tokens, position: 0, lookup };
+    let operand = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("Unexpected {:?}", token));
+    }
+    Ok(match operand {
+        Operand::Expression(expression) => expression,
+        test => Expression { operator: Operator::Or, operands: vec!(test) }, // A lone test is an OR
+    })
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    lookup: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek_is(&self, token: &Token) -> bool {
+        self.tokens.get(self.position) == Some(token)
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(format!("Expected {:?}, found {:?}", expected, token)),
+        }
+    }
+
+    fn test(&self, name: &str) -> Result<i32, String> {
+        self.lookup.iter()
+            .position(|test| test == name)
+            .map(|id| id as i32)
+            .ok_or_else(|| format!("Unknown test \"{}\"", name))
+    }
+
+    fn or(&mut self) -> Result<Operand, String> {
+        let mut operands = vec!(self.and()?);
+        while self.peek_is(&Token::Or) {
+            self.position += 1;
+            operands.push(self.and()?);
+        }
+        Ok(joined(Operator::Or, operands))
+    }
+
+    fn and(&mut self) -> Result<Operand, String> {
+        let mut operands = vec!(self.unary()?);
+        while self.peek_is(&Token::And) {
+            self.position += 1;
+            operands.push(self.unary()?);
+        }
+        Ok(joined(Operator::And, operands))
+    }
+
+    fn unary(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Name(name)) => Ok(Operand::Test(self.test(name)?)),
+            Some(Token::Not) => match self.next() {
+                Some(Token::Name(name)) => Ok(Operand::Not(self.test(name)?)),
+                token => Err(format!("Only tests can be negated, found {:?}", token)),
+            },
+            Some(Token::Open) => {
+                let operand = self.or()?;
+                self.expect(&Token::Close)?;
+                Ok(operand)
+            },
+            Some(&Token::Number(threshold)) => {
+                self.expect(&Token::Of)?;
+                self.expect(&Token::Open)?;
+                let mut operands = vec!(self.or()?);
+                while self.peek_is(&Token::Comma) {
+                    self.position += 1;
+                    operands.push(self.or()?);
+                }
+                self.expect(&Token::Close)?;
+                Ok(Operand::Expression(Expression { operator: Operator::AtLeast(threshold), operands }))
+            },
+            token => Err(format!("Expected a test, found {:?}", token)),
+        }
+    }
+}
+
+fn joined(operator: Operator, mut operands: Vec<Operand>) -> Operand {
+    if operands.len() == 1 {
+        return operands.remove(0);
+    }
+    Operand::Expression(Expression { operator, operands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        vec!("A", "B", "Manual Starter", "2", "D-1").into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn should_read_back_what_was_written() {
+        let expressions = vec!(
+            "A",
+            "!A | (B & 2 of (\"Manual Starter\", \"2\", D-1))",
+            "A & B & !D-1",
+            "(A | B) & (\"Manual Starter\" | D-1)",
+        );
+        for text in expressions {
+            assert_eq!(expression_infix(&parse_infix(text, &names()).unwrap(), &names()), text);
+        }
+    }
+
+    #[test]
+    fn should_bind_and_tighter_than_or() {
+        let expression = parse_infix("A | B & D-1", &names()).unwrap();
+        assert_eq!(expression, Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Test(0), Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(1), Operand::Test(4)),
+            })),
+        });
+    }
+
+    #[test]
+    fn should_split_constraints() {
+        assert_eq!(tokens("A->B !-> \"C\"=D").unwrap(), vec!(
+            Token::Name("A".to_owned()),
+            Token::DependsOn,
+            Token::Name("B".to_owned()),
+            Token::NeverDependsOn,
+            Token::Name("C".to_owned()),
+            Token::Is,
+            Token::Name("D".to_owned()),
+        ));
+    }
+
+    #[test]
+    fn should_explain_what_is_wrong() {
+        assert_eq!(parse_infix("A & E", &names()), Err("Unknown test \"E\"".to_owned()));
+        assert!(parse_infix("!(A | B)", &names()).is_err());
+        assert!(parse_infix("A & (B", &names()).is_err());
+        assert!(parse_infix("\"A", &names()).is_err());
+        assert!(parse_infix("A B", &names()).is_err());
+    }
+}
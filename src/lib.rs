@@ -0,0 +1,30 @@
+extern crate rustc_serialize;
+
+pub mod test_results;
+pub mod expression;
+pub mod infix;
+pub mod dependency_expression;
+pub mod quine_mccluskey;
+pub mod budget;
+pub mod reducer;
+pub mod espresso;
+pub mod factor;
+pub mod threshold;
+pub mod groups;
+pub mod constraints;
+pub mod check;
+pub mod plan;
+pub mod runner;
+pub mod algebra;
+pub mod flakiness;
+pub mod outliers;
+pub mod evidence;
+pub mod inference;
+pub mod online;
+pub mod environments;
+pub mod drift;
+pub mod experiments;
+pub mod bitset;
+pub mod execution_matrix;
+pub mod store;
+pub mod cache;
@@ -1,67 +1,647 @@
 extern crate rustc_serialize;
+extern crate cdt406;
 use rustc_serialize::json::ToJson;
 use rustc_serialize::json::Json;
 use std::collections::BTreeMap;
 
-mod test_results;
 mod args_parse;
-mod expression;
-mod dependency_expression;
-mod quine_mccluskey;
-use test_results::*;
+use cdt406::test_results::*;
 use args_parse::*;
-use dependency_expression::*;
-use quine_mccluskey::*;
-use expression::*;
+use cdt406::flakiness::*;
+use cdt406::outliers::*;
+use cdt406::inference::*;
+use cdt406::experiments::*;
+use cdt406::execution_matrix::*;
+use cdt406::expression::*;
+use cdt406::groups::*;
+use cdt406::constraints::*;
+use cdt406::check::*;
+use cdt406::plan::*;
+use cdt406::runner::*;
+use cdt406::store::*;
+use cdt406::cache::*;
+use cdt406::online::*;
+use cdt406::environments::*;
+use cdt406::drift::*;
 use std::env;
 use std::process;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::path::Path;
+use std::io::{self, BufRead, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
-    let parse_result = parse_cli_args(env::args().collect());
+    let parse_result = parse_options(env::args().collect())
+        .and_then(|(positional, options)| parse_command(positional).map(|command| (command, options)));
     if parse_result.is_err() {
-        println!("Usage: ./test-dependencies input-filename output-filename");
+        println!("Usage: ./test-dependencies [options] input-filename output-filename");
+        println!("       ./test-dependencies check [options] manifest-filename input-filename [report-filename]");
+        println!("       ./test-dependencies run [options] config-filename history-filename [report-filename]");
+        println!("       ./test-dependencies import csv-filename store-filename");
+        println!("       ./test-dependencies learn [options] [history-filename] < executions");
+        println!("  --executions FROM..TO    only use the executions from index FROM up to (not including) TO");
+        println!("  --metadata FILE          label executions from FILE, a CSV of \"index\" or \"id\" then keys");
+        println!("  --where KEY=VALUE        only use executions labelled with VALUE for KEY (repeatable)");
+        println!("  --since TIME             only use executions with a \"timestamp\" of TIME or later");
+        println!("  --until TIME             only use executions with a \"timestamp\" up to TIME");
+        println!("  --partition-by KEY       infer separately for each value of the KEY label (not with --cache,");
+        println!("                           --drift, --groups, --infix, --plan or --suggest-experiments)");
+        println!("  --comparison FILE        with --partition-by, write the universal and label-specific");
+        println!("                           dependencies to FILE");
+        println!("  --merged                 with --partition-by, write one model per test, with conditions");
+        println!("                           on the label where the values disagree");
+        println!("  --drift FILE             write where each test's dependency changed, and a timeline of");
+        println!("                           its models, to FILE");
+        println!("  --window N               executions that --drift infers from at a time (default 50)");
+        println!("  --step N                 executions between --drift windows (default half the window)");
+        println!("  --cache FILE             keep models in FILE, and only infer tests whose history changed");
+        println!("  --flaky-report FILE      write tests with contradictory outcomes to FILE");
+        println!("  --exclude-flaky          leave flaky tests out of dependency inference");
+        println!("  --flaky-threshold N      inconsistency a test must exceed to be excluded (default 0)");
+        println!("  --exclude-execution N    leave out the execution at index N (repeatable)");
+        println!("  --exclude-execution-id ID  leave out the execution with the given \"@id\" (repeatable)");
+        println!("  --exclude-failed-fraction F  leave out executions where at least F of all tests failed");
+        println!("  --exclude-deviation K    leave out executions that deviate K times more than usual from");
+        println!("                           each test's typical outcome");
+        println!("  --min-passing-executions N  passes needed before reporting a dependency (default 1)");
+        println!("  --min-passing-contexts N    distinct sets of other passing tests needed (default 1)");
+        println!("  --suggest-experiments FILE  write the tests to force-fail next to FILE");
+        println!("  --plan FILE              write a run order and when to skip each test to FILE");
+        println!("  --experiments N          how many experiments to suggest (default 3)");
+        println!("  --max-hypotheses N       hypotheses to consider per test (default 10000)");
+        println!("  --alternatives           list every equally small dependency, not just the first (only");
+        println!("                           ever more than one with --negative)");
+        println!("  --factor                 nest dependencies, e.g. A & (B | C) instead of (A & B) | (A & C)");
+        println!("  --negative               allow dependencies on other tests failing, e.g. A & !B");
+        println!("  --at-least               write \"any k of these n\" dependencies as a single threshold");
+        println!("  --infix                  also write each dependency out as text, e.g. \"A & (B | C)\"");
+        println!("  --constraints FILE       honor known required, forbidden and fixed dependencies from FILE");
+        println!("  --fail-on KINDS          check findings that fail it: unobserved, undeclared and/or");
+        println!("                           contradicted, comma separated (default all three)");
+        println!("  --groups                 name sub-expressions shared by several tests under \"@groups\"");
+        println!("  --threads N              infer tests on N threads (default: one per CPU)");
+        println!("  --reducer NAME           qm, absorption, espresso or auto (default auto)");
+        println!("  --exact-variables N      most variables auto will still reduce exactly (default 10)");
+        println!("  --espresso-iterations N  most improvement rounds espresso tries (default 20)");
+        println!("  --time-budget-ms N       milliseconds to spend reducing each test before falling back");
+        println!("  --step-budget N          steps to spend reducing each test before falling back");
         process::exit(-1);
     }
-    let args = parse_result.unwrap();
-    let source = CsvTestSource::new(&args.input_filename);
-    let tests = source.read_tests().unwrap();
-    let tests_slices: Vec<&[bool]> = tests.results
-        .iter()
-        .map(|vec| &vec.executions[..])
+    match parse_result.unwrap() {
+        (Command::Infer(args), options) => infer_dependencies(&args, &options),
+        (Command::Check(args), options) => check_manifest(&args, &options),
+        (Command::Run(args), options) => run_tests(&args, &options),
+        (Command::Import(args), _) => import_history(&args),
+        (Command::Learn(history_filename), options) => learn(&history_filename, &options),
+    }
+}
+
+/// The history that's left once broken executions and (if asked) flaky tests are set aside
+struct History {
+    tests: AllTestResults,
+    all_names: Vec<String>, // Including the excluded tests
+    excluded_tests: Vec<usize>,
+    excluded_executions: Vec<(usize, String)>,
+    execution_metadata: Vec<BTreeMap<String, String>>, // Before any executions were excluded
+    execution_indexes: Vec<usize>, // Where each execution is in the whole history, before any were excluded
+}
+
+/// Reads either a history store or a CSV history, with any `--metadata`, keeping only the executions in
+/// `--executions` that match `--where`, `--since` and `--until`. Also gives where each of those executions is in the
+/// whole history
+fn read_results(filename: &str, options: &Options) -> (AllTestResults, Vec<usize>) {
+    let tests = if HistoryStore::is_store(filename) {
+        let store = HistoryStore::read(filename).unwrap_or_else(|reason| {
+            println!("Failed to read {}: {}", filename, reason);
+            process::exit(-1);
+        });
+        store.results(0..store.executions.len())
+    } else {
+        CsvTestSource::new(filename).read_tests().unwrap_or_else(|reason| {
+            println!("Failed to read {}: {}", filename, reason);
+            process::exit(-1);
+        })
+    };
+    let tests = match options.metadata {
+        Some(ref metadata_filename) => {
+            let parsed = File::open(metadata_filename)
+                .map_err(|e| e.to_string())
+                .and_then(|file| parse_metadata(io::BufReader::new(file), &tests).map_err(|e| format!("{:?}", e)));
+            parsed.unwrap_or_else(|reason| {
+                println!("Failed to read metadata from {}: {}", metadata_filename, reason);
+                process::exit(-1);
+            })
+        },
+        None => tests,
+    };
+
+    let (tests, execution_indexes) = match options.executions {
+        Some(range) => {
+            let range = range.within(tests.execution_count());
+            (tests.without_executions(&(0..tests.execution_count())
+                .filter(|execution_index| !range.contains(execution_index))
+                .collect::<Vec<usize>>()), range)
+        },
+        None => {
+            let execution_indexes = 0..tests.execution_count();
+            (tests, execution_indexes)
+        },
+    };
+    let execution_indexes = execution_indexes
+        .enumerate()
+        .filter(|&(execution_index, _)| options.filter.matches(&tests.execution_metadata(execution_index)))
+        .map(|(_, original)| original)
         .collect();
+    (tests.matching(&options.filter), execution_indexes)
+}
+
+fn read_history(input_filename: &str, options: &Options) -> History {
+    let (tests, execution_indexes) = read_results(input_filename, options);
 
-    let test_dependencies: Vec<(String, Option<Expression>)> = (0..tests.count)
-        .map(|i| dependency_expression(&tests_slices, i))
-        .map(|test_dependency| test_dependency.map_or(None, |test_dependency| reduce(&test_dependency)))
-        .zip(tests.results.iter().map(|test| test.name.clone()))
-        .map(|(str, exp)| (exp, str))
+    let excluded_executions = execution_exclusions(&tests, options);
+    let execution_metadata = (0..tests.execution_count())
+        .map(|execution_index| tests.execution_metadata(execution_index))
         .collect();
+    let tests = tests.without_executions(&excluded_executions.iter()
+        .map(|&(execution_index, _)| execution_index)
+        .collect::<Vec<usize>>());
+
+    let all_names = tests.names();
+
+    // Ranking compares every execution of every test, so it's only done when it's asked for
+    let mut excluded_tests = Vec::new();
+    if options.flaky_report.is_some() || options.exclude_flaky {
+        let ranked_flaky = rank_flaky(&ExecutionMatrix::new(&tests.slices()), &tests.skipped_tests());
+        if let Some(ref filename) = options.flaky_report {
+            write_json(filename, &flakiness_json(&ranked_flaky, &all_names));
+        }
+        if options.exclude_flaky {
+            excluded_tests = flaky_tests(&ranked_flaky, options.flaky_threshold);
+        }
+    }
+    History {
+        tests: tests.without_tests(&excluded_tests),
+        all_names,
+        excluded_tests,
+        excluded_executions,
+        execution_metadata,
+        execution_indexes,
+    }
+}
 
-    let mut map = BTreeMap::<String, Option<Json>>::new();
-    let names = test_dependencies.iter()
-        .map(|&(ref name, _)| name.clone())
+fn inference_options(options: &Options, constraints: Vec<Constraint>) -> InferenceOptions {
+    InferenceOptions {
+        thresholds: options.thresholds,
+        alternatives: options.alternatives,
+        factor: options.factor,
+        negative: options.negative,
+        at_least: options.at_least,
+        reducer: options.reducer.build(&options.reducer_settings),
+        fallbacks: options.reducer.fallbacks(&options.reducer_settings),
+        budget: options.budget,
+        constraints,
+    }
+}
+
+/// `infer_all`, or with `--cache`, only the tests that changed since the cached run
+fn infer_models(matrix: &ExecutionMatrix, inference_options: &InferenceOptions, options: &Options, names: &[String])
+                -> Vec<TestModel> {
+    let filename = match options.cache {
+        Some(ref filename) => filename,
+        None => return infer_all(matrix, inference_options, options.threads),
+    };
+    let mut cache = ModelCache::read(filename).unwrap_or_else(|reason| {
+        println!("Ignoring the cache in {}: {}", filename, reason);
+        ModelCache::default()
+    });
+    let fingerprint = format!("{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}", names, options.thresholds,
+                              options.alternatives, options.factor, options.negative, options.at_least, options.reducer,
+                              options.reducer_settings, options.budget, inference_options.constraints);
+    let (models, inferred) = infer_all_cached(matrix, inference_options, options.threads, &fingerprint, &mut cache);
+    println!("Inferred {} of {} tests, the rest were cached", inferred.len(), models.len());
+    if let Err(reason) = cache.write(filename) {
+        println!("Failed to write the cache to {}: {}", filename, reason);
+    }
+    models
+}
+
+fn infer_dependencies(args: &Arguments, options: &Options) {
+    let History { tests, all_names, excluded_tests, excluded_executions, execution_metadata, execution_indexes } =
+        read_history(&args.input_filename, options);
+    if let Some(ref key) = options.partition_by {
+        let excluded = excluded_json(&excluded_tests, &excluded_executions, &all_names, &execution_metadata);
+        return infer_environment_dependencies(args, options, &tests, key, excluded);
+    }
+    let tests_slices = tests.slices();
+    let names = tests.names();
+    let constraints = match options.constraints {
+        Some(ref filename) => read_constraints(filename, &names),
+        None => Vec::new(),
+    };
+    let inference_options = inference_options(options, constraints);
+
+    let matrix = ExecutionMatrix::new(&tests_slices);
+
+    let mut models = infer_models(&matrix, &inference_options, options, &names);
+    if let Some(ref filename) = options.plan {
+        write_json(filename, &plan_json(&plan(&models), &names));
+    }
+    if let Some(ref filename) = options.drift {
+        let step = options.step.unwrap_or(options.window / 2);
+        let timelines = drift(&tests_slices, options.window, step, &inference_options, options.threads);
+        // Report executions by where they are in the whole history, whatever was filtered or excluded
+        let kept: Vec<usize> = (0..execution_metadata.len())
+            .filter(|execution_index| !excluded_executions.iter().any(|&(excluded, _)| excluded == *execution_index))
+            .collect();
+        let cited_indexes: Vec<usize> = kept.iter()
+            .map(|&execution_index| execution_indexes[execution_index])
+            .collect();
+        let cited_metadata: Vec<BTreeMap<String, String>> = kept.iter()
+            .map(|&execution_index| execution_metadata[execution_index].clone())
+            .collect();
+        write_json(filename, &drift_json(&timelines, &names, &cited_indexes, &cited_metadata));
+    }
+    let mut map = BTreeMap::<String, Json>::new();
+    let mut lookup = names.clone();
+    if options.groups {
+        let expressions: Vec<Option<Expression>> = models.iter().map(|model| model.expression.clone()).collect();
+        let grouped = extract_groups(&expressions, names.len());
+        for (model, expression) in models.iter_mut().zip(grouped.expressions) {
+            model.expression = expression;
+        }
+        lookup.extend((0..grouped.groups.len()).map(group_name));
+        if !grouped.groups.is_empty() {
+            map.insert("@groups".to_owned(), grouped.groups.iter()
+                .enumerate()
+                .map(|(i, group)| (group_name(i), expression_json(group, &lookup)))
+                .collect::<BTreeMap<String, Json>>()
+                .to_json());
+        }
+    }
+    for (name, model) in names.iter().zip(models.iter()) {
+        let mut json = model_json(model, &lookup);
+        if let (true, Some(expression), &mut Json::Object(ref mut object)) =
+            (options.infix, &model.expression, &mut json) {
+            object.insert("infix".to_owned(), expression_infix(expression, &lookup).to_json());
+        }
+        map.insert(name.clone(), json);
+    }
+
+    let violations: Vec<Json> = models.iter()
+        .flat_map(|model| model.violations.iter())
+        .map(|violation| violation_json(violation, &names))
         .collect();
+    if !violations.is_empty() {
+        map.insert("@constraint_violations".to_owned(), violations.to_json());
+    }
+
+    if let Some(ref filename) = options.suggest_experiments {
+        write_json(filename, &suggestions_json(&tests_slices, &names, options.experiments, options.max_hypotheses));
+    }
+
+    if let Some(excluded) = excluded_json(&excluded_tests, &excluded_executions, &all_names, &execution_metadata) {
+        map.insert("@excluded".to_owned(), excluded);
+    }
+
+    write_json(&args.output_filename, &map.to_json());
+    println!("Done!");
+}
+
+/// With `--partition-by`, the models of each value of the label, or with `--merged`, one model per test with
+/// conditions on the label
+fn infer_environment_dependencies(args: &Arguments, options: &Options, tests: &AllTestResults, key: &str,
+                                  excluded: Option<Json>) {
+    let names = tests.names();
+    let constraints = match options.constraints {
+        Some(ref filename) => read_constraints(filename, &names),
+        None => Vec::new(),
+    };
+    let environments = infer_environments(tests, key, &inference_options(options, constraints), options.threads);
+    if environments.is_empty() {
+        println!("No executions are labelled with \"{}\"", key);
+        process::exit(-1);
+    }
+
+    let mut map = if options.merged {
+        merged_json(&environments, key, &names)
+    } else {
+        environments.iter()
+            .map(|environment| (environment.value.clone(), names.iter()
+                .zip(environment.models.iter())
+                .map(|(name, model)| (name.clone(), model_json(model, &names)))
+                .collect::<BTreeMap<String, Json>>()
+                .to_json()))
+            .collect()
+    };
+    if let Some(excluded) = excluded {
+        map.insert("@excluded".to_owned(), excluded);
+    }
+    if let Some(ref filename) = options.comparison {
+        write_json(filename, &comparison_json(&compare(&environments), &environments, key, &names));
+    }
+
+    write_json(&args.output_filename, &map.to_json());
+    println!("Done!");
+}
+
+/// `{"tests": [...], "executions": [{"index", "reason", ...metadata}]}`, or nothing if nothing was left out
+fn excluded_json(excluded_tests: &[usize], excluded_executions: &[(usize, String)], all_names: &[String],
+                 execution_metadata: &[BTreeMap<String, String>]) -> Option<Json> {
+    if excluded_tests.is_empty() && excluded_executions.is_empty() {
+        return None;
+    }
+    let mut excluded = BTreeMap::new();
+    excluded.insert("tests".to_owned(), excluded_tests.iter()
+        .map(|&id| all_names[id].clone())
+        .collect::<Vec<String>>()
+        .to_json());
+    excluded.insert("executions".to_owned(), excluded_executions.iter()
+        .map(|&(execution_index, ref reason)| {
+            let mut execution = BTreeMap::new();
+            execution.insert("index".to_owned(), execution_index.to_json());
+            execution.insert("reason".to_owned(), reason.to_json());
+            // The id, commit, timestamp and so on, to find the run again
+            for (key, value) in &execution_metadata[execution_index] {
+                execution.entry(key.clone()).or_insert_with(|| value.to_json());
+            }
+            Json::Object(execution)
+        })
+        .collect::<Vec<Json>>()
+        .to_json());
+    Some(Json::Object(excluded))
+}
 
-    for (name, dep) in test_dependencies {
-        if dep.is_none() {
-            map.insert(name.clone(), Option::None);
+/// Exits with the findings that `--fail-on` asked for as bits of the exit code, so CI can gate on them
+fn check_manifest(args: &CheckArguments, options: &Options) {
+    let history = read_history(&args.input_filename, options);
+    let tests_slices = history.tests.slices();
+    let names = history.tests.names();
+    let declared = match declared_edges(&read_constraints(&args.manifest_filename, &names), &names) {
+        Ok(declared) => declared,
+        Err(reason) => {
+            println!("Failed to read the manifest {}: {}", args.manifest_filename, reason);
+            process::exit(-1);
+        },
+    };
+
+    // Inferring with the manifest as constraints would only agree with it
+    let matrix = ExecutionMatrix::new(&tests_slices);
+    let models = infer_models(&matrix, &inference_options(options, Vec::new()), options, &names);
+    let found = check(&declared, &models, &matrix);
+
+    for edge in &found.unobserved {
+        println!("unobserved: {} -> {} was declared, but never inferred", names[edge.test], names[edge.dependency]);
+    }
+    for edge in &found.undeclared {
+        println!("undeclared: {} -> {} was inferred, but never declared", names[edge.test], names[edge.dependency]);
+    }
+    for &(ref edge, executions) in &found.contradicted {
+        println!("contradicted: {} passed without {} in {} execution(s)",
+                 names[edge.test], names[edge.dependency], executions);
+    }
+    if let Some(ref filename) = args.report_filename {
+        write_json(filename, &check_json(&found, &names));
+    }
+    println!("{} unobserved, {} undeclared, {} contradicted",
+             found.unobserved.len(), found.undeclared.len(), found.contradicted.len());
+    process::exit(found.exit_code(&options.fail_on));
+}
+
+/// Runs each test's command in the planned order, skipping tests whose dependency can no longer pass, and adds the
+/// results to the history as a new execution. Exits with 1 if any test failed
+fn run_tests(args: &RunArguments, options: &Options) {
+    let mut text = String::new();
+    let commands = File::open(&args.config_filename)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| e.to_string())
+        .and_then(|_| parse_commands(&text));
+    let commands = commands.unwrap_or_else(|reason| {
+        println!("Failed to read commands from {}: {}", args.config_filename, reason);
+        process::exit(-1);
+    });
+
+    // Without a history yet, every test simply runs
+    let (names, models) = if Path::new(&args.history_filename).exists() {
+        let history = read_history(&args.history_filename, options);
+        let tests_slices = history.tests.slices();
+        let names = history.tests.names();
+        let constraints = match options.constraints {
+            Some(ref filename) => read_constraints(filename, &names),
+            None => Vec::new(),
+        };
+        let matrix = ExecutionMatrix::new(&tests_slices);
+        (names.clone(), infer_models(&matrix, &inference_options(options, constraints), options, &names))
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    let outcomes = run_suite(&names, &models, &commands, |name, command| {
+        println!("Running {}: {}", name, command);
+        process::Command::new("sh").arg("-c").arg(command).status().is_ok_and(|status| status.success())
+    }).unwrap_or_else(|reason| {
+        println!("Failed to run {}: {}", args.config_filename, reason);
+        process::exit(-1);
+    });
+    for &(ref name, outcome) in &outcomes {
+        println!("{}: {}", name, outcome.name());
+    }
+
+    let results: Vec<(String, bool)> = outcomes.iter()
+        .map(|&(ref name, outcome)| (name.clone(), outcome.passed()))
+        .collect();
+    let mut metadata = BTreeMap::new();
+    metadata.insert("timestamp".to_owned(), utc_timestamp());
+    // Recorded as failed, but marked so that they aren't mistaken for real failures
+    let skipped: Vec<&str> = outcomes.iter()
+        .filter(|&&(_, outcome)| outcome == Outcome::Skipped)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !skipped.is_empty() {
+        metadata.insert("skipped".to_owned(), skipped.join(";"));
+    }
+    let written = if HistoryStore::is_store(&args.history_filename) {
+        // Only the new execution is written
+        HistoryStore::read(&args.history_filename).and_then(|mut store| {
+            let id = format!("run-{}", store.executions.len() + 1);
+            store.append(&args.history_filename, &results, Some(&id), &metadata)
+        })
+    } else {
+        let history = if Path::new(&args.history_filename).exists() {
+            CsvTestSource::new(&args.history_filename).read_tests()
         } else {
-            map.insert(name.clone(), Option::Some(expression_json(&dep.unwrap(), &names)));
+            Ok(AllTestResults::new(Vec::new()))
+        };
+        history.and_then(|history| {
+            let id = format!("run-{}", history.execution_count() + 1);
+            // Written next to the history and then moved over it, so a failed write never loses the earlier executions
+            let temporary = format!("{}.tmp", args.history_filename);
+            File::create(&temporary)
+                .and_then(|mut file| {
+                    file.write_all(history.with_execution(&results, Some(id), &metadata).to_csv().as_bytes())
+                })
+                .and_then(|_| fs::rename(&temporary, &args.history_filename))
+                .map_err(|e| {
+                    let _ = fs::remove_file(&temporary);
+                    e.to_string()
+                })
+        })
+    };
+    if let Some(ref filename) = args.report_filename {
+        write_json(filename, &outcomes_json(&outcomes));
+    }
+    if let Err(reason) = written {
+        println!("Failed to write to {}: {}", args.history_filename, reason);
+        process::exit(-1);
+    }
+    if outcomes.iter().any(|&(_, outcome)| outcome == Outcome::Failed) {
+        process::exit(1);
+    }
+}
+
+/// The time now, as "2026-09-01T10:00:00Z", so that timestamps sort as text
+fn utc_timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    // Days since 1970-01-01 to a civil date, counting in 400 year eras that start on March 1st
+    let days = seconds / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day,
+            seconds % 86400 / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// Writes every execution of a CSV history to a new history store
+fn import_history(args: &Arguments) {
+    let tests = CsvTestSource::new(&args.input_filename).read_tests().unwrap_or_else(|reason| {
+        println!("Failed to read {}: {}", args.input_filename, reason);
+        process::exit(-1);
+    });
+    let written = File::create(&args.output_filename)
+        .and_then(|mut file| file.write_all(&HistoryStore::encode_results(&tests)));
+    match written {
+        Ok(_) => println!("Imported {} executions of {} tests", tests.execution_count(), tests.results.len()),
+        Err(reason) => {
+            println!("Failed to write to {}: {}", args.output_filename, reason);
+            process::exit(-1);
+        },
+    }
+}
+
+/// Learns from the history, if there is one, then from standard input, one execution per line. Without a history,
+/// the first line names the tests. Every change to a test's dependency is printed as a line of JSON
+fn learn(history_filename: &Option<String>, options: &Options) {
+    let mut names = Vec::new();
+    let mut learner = None;
+    let mut execution_index = 0;
+    if let Some(ref filename) = *history_filename {
+        let (tests, _) = read_results(filename, options);
+        let mut history = OnlineLearner::new(tests.results.len(), options.thresholds);
+        for index in 0..tests.execution_count() {
+            let results: Vec<bool> = tests.results.iter().map(|test| test.executions[index]).collect();
+            if let Err(reason) = history.observe(&results) {
+                eprintln!("Skipping execution {} of {}: {}", index, filename, reason);
+            }
         }
+        names = tests.names();
+        learner = Some(history);
+        execution_index = tests.execution_count();
     }
 
-    let f = File::create(&args.output_filename);
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(reason) => {
+                eprintln!("Failed to read standard input: {}", reason);
+                break;
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let learner = match learner {
+            Some(ref mut learner) => learner,
+            None => {
+                names = line.split(',').map(|name| name.trim().to_owned()).collect();
+                learner = Some(OnlineLearner::new(names.len(), options.thresholds));
+                continue;
+            },
+        };
+
+        let results: Result<Vec<bool>, String> = line.split(',')
+            .map(|value| match value.trim() {
+                "1" => Ok(true),
+                "0" => Ok(false),
+                value => Err(format!("Expected 1 or 0, found \"{}\"", value)),
+            })
+            .collect();
+        match results.and_then(|results| learner.observe(&results)) {
+            Ok(changes) => {
+                for change in changes {
+                    println!("{}", change_json(&change, execution_index, &names));
+                }
+                execution_index += 1;
+            },
+            Err(reason) => eprintln!("Skipping \"{}\": {}", line, reason),
+        }
+    }
+}
+
+/// Executions to leave out of the analysis, in order, with the reason each was left out
+fn execution_exclusions(tests: &AllTestResults, options: &Options) -> Vec<(usize, String)> {
+    let slices = tests.slices();
+    let mut exclusions = BTreeMap::<usize, String>::new();
+
+    for &execution_index in &options.exclude_executions {
+        exclusions.entry(execution_index).or_insert_with(|| "index".to_owned());
+    }
+    for execution_index in 0..tests.execution_count() {
+        let id = tests.execution_id(execution_index);
+        if id.is_some_and(|id| options.exclude_execution_ids.iter().any(|excluded| excluded == id)) {
+            exclusions.entry(execution_index).or_insert_with(|| "id".to_owned());
+        }
+    }
+    if !options.detection_rules.is_empty() {
+        for (execution_index, rule) in detect_outliers(&slices, &options.detection_rules, &tests.skipped_tests()) {
+            exclusions.entry(execution_index).or_insert_with(|| rule.name().to_owned());
+        }
+    }
+
+    exclusions.into_iter()
+        .filter(|&(execution_index, _)| execution_index < tests.execution_count())
+        .collect()
+}
+
+/// Exits with the reason if the constraints can't be read, since inferring without them would be misleading
+fn read_constraints(filename: &str, names: &[String]) -> Vec<Constraint> {
+    let mut text = String::new();
+    let result = File::open(filename)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| e.to_string())
+        .and_then(|_| parse_constraints(&text, names));
+    match result {
+        Ok(constraints) => constraints,
+        Err(reason) => {
+            println!("Failed to read constraints from {}: {}", filename, reason);
+            process::exit(-1);
+        },
+    }
+}
+
+fn write_json(filename: &str, json: &Json) {
+    let f = File::create(filename);
     if f.is_err() {
-        println!("Failed to create {}", args.output_filename);
+        println!("Failed to create {}", filename);
         return;
     }
     let mut f = f.unwrap();
-    let result = f.write_all(map.to_json().to_string().as_bytes());
+    let result = f.write_all(json.to_string().as_bytes());
     if result.is_err() {
-        println!("Failed to write to {}", args.output_filename);
+        println!("Failed to write to {}", filename);
     }
-
-    println!("Done!");
 }
\ No newline at end of file
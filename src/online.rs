@@ -0,0 +1,174 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::{BTreeMap, BTreeSet};
+use bitset::BitVector;
+use evidence::*;
+use expression::*;
+
+/// A test's dependency changing after an execution
+#[derive(Debug, PartialEq, Clone)]
+pub struct Change {
+    pub test: usize,
+    pub status: Status,
+    pub expression: Option<Expression>, // Only set when the status is `DependsOn`
+    pub previous: Option<Expression>,
+}
+
+/// What's learned about one test so far
+#[derive(Debug, Default)]
+struct Learned {
+    contexts: BTreeSet<BitVector>, // Every distinct set of other tests that it passed alongside
+    minimal: Vec<BitVector>, // The contexts that no other context is a subset of
+    evidence: Evidence,
+}
+
+/// Learns positive dependencies one execution at a time. Each test keeps only its minimal passing contexts, which are
+/// exactly the terms of its smallest dependency, so nothing is ever reduced
+pub struct OnlineLearner {
+    test_count: usize,
+    thresholds: EvidenceThresholds,
+    learned: Vec<Learned>,
+    models: Vec<(Status, Option<Expression>)>,
+}
+
+impl OnlineLearner {
+    pub fn new(test_count: usize, thresholds: EvidenceThresholds) -> OnlineLearner {
+        OnlineLearner {
+            test_count,
+            thresholds,
+            learned: (0..test_count).map(|_| Learned::default()).collect(),
+            models: vec!((Status::NeverPassed, None); test_count),
+        }
+    }
+
+    pub fn test_count(&self) -> usize {
+        self.test_count
+    }
+
+    pub fn status(&self, test: usize) -> Status {
+        self.models[test].0
+    }
+
+    pub fn expression(&self, test: usize) -> Option<&Expression> {
+        self.models[test].1.as_ref()
+    }
+
+    /// Adds one execution, with a result for every test, and returns the tests whose status or dependency changed
+    pub fn observe(&mut self, results: &[bool]) -> Result<Vec<Change>, String> {
+        if results.len() != self.test_count {
+            return Err(format!("Expected {} results, found {}", self.test_count, results.len()));
+        }
+
+        let passing = BitVector::from_bools(results);
+        let mut changes = Vec::new();
+        for target in passing.ones() {
+            let mut context = passing.clone();
+            context.set(target, false);
+            let learned = &mut self.learned[target];
+            learned.evidence.passing_executions += 1;
+            if learned.contexts.insert(context.clone()) {
+                learned.evidence.passing_contexts += 1;
+                let count = context.count_ones();
+                if count + 1 < self.test_count {
+                    learned.evidence.informative_contexts += 1;
+                }
+                learned.evidence.independent |= count == 0;
+                if !learned.minimal.iter().any(|kept| kept.is_subset(&context)) {
+                    learned.minimal.retain(|kept| !context.is_subset(kept));
+                    learned.minimal.push(context);
+                    learned.minimal.sort();
+                }
+            }
+
+            let status = status(&learned.evidence, &self.thresholds);
+            let expression = match status {
+                Status::DependsOn => Some(minimal_expression(&learned.minimal)),
+                _ => None,
+            };
+            if (status, &expression) != (self.models[target].0, &self.models[target].1) {
+                let previous = self.models[target].1.take();
+                self.models[target] = (status, expression.clone());
+                changes.push(Change { test: target, status, expression, previous });
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// `A | (B & C)`, with a single test standing alone rather than in an AND
+fn minimal_expression(minimal: &[BitVector]) -> Expression {
+    Expression {
+        operator: Operator::Or,
+        operands: minimal.iter()
+            .map(|context| {
+                let tests: Vec<Operand> = context.ones().map(|test| Operand::Test(test as i32)).collect();
+                if tests.len() == 1 {
+                    tests[0].clone()
+                } else {
+                    Operand::Expression(Expression { operator: Operator::And, operands: tests })
+                }
+            })
+            .collect(),
+    }
+}
+
+/// `{"execution": 12, "test": "Horn", "status": "depends-on", "dependency": operator or null}`
+pub fn change_json(change: &Change, execution_index: usize, lookup: &[String]) -> Json {
+    let mut map = BTreeMap::new();
+    map.insert("execution".to_owned(), execution_index.to_json());
+    map.insert("test".to_owned(), lookup[change.test].to_json());
+    map.insert("status".to_owned(), change.status.name().to_json());
+    map.insert("dependency".to_owned(), change.expression.as_ref()
+        .map_or(Json::Null, |expression| expression_json(expression, lookup)));
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use algebra::equivalent;
+    use execution_matrix::ExecutionMatrix;
+    use inference::*;
+
+    #[test]
+    fn should_learn_what_batch_inference_finds() {
+        let slice: &[&[bool]] = &[
+            &[true, true, false, true, false, true],
+            &[true, false, true, true, true, false],
+            &[false, true, true, true, false, true],
+            &[true, true, true, false, true, true],
+        ];
+        let mut learner = OnlineLearner::new(slice.len(), EvidenceThresholds::default());
+        for execution_index in 0..slice[0].len() {
+            let results: Vec<bool> = slice.iter().map(|test| test[execution_index]).collect();
+            learner.observe(&results).unwrap();
+        }
+
+        let models = infer_all(&ExecutionMatrix::new(slice), &InferenceOptions::default(), 1);
+        for (test, model) in models.iter().enumerate() {
+            assert_eq!(learner.status(test), model.status);
+            match (learner.expression(test), &model.expression) {
+                (Some(learned), Some(inferred)) => assert!(equivalent(learned, inferred)),
+                (learned, inferred) => assert_eq!(learned, inferred.as_ref()),
+            }
+        }
+    }
+
+    #[test]
+    fn should_only_report_changes() {
+        let mut learner = OnlineLearner::new(3, EvidenceThresholds::default());
+        assert_eq!(learner.observe(&[true, true, false]).unwrap().len(), 2); // Both never passed before
+        assert!(learner.observe(&[true, true, false]).unwrap().is_empty());
+
+        // Passing alongside only C too, A now needs B or C
+        let changes = learner.observe(&[true, false, true]).unwrap();
+        assert_eq!(changes[0], Change {
+            test: 0,
+            status: Status::DependsOn,
+            expression: Some(Expression { operator: Operator::Or, operands: vec!(Operand::Test(1), Operand::Test(2)) }),
+            previous: Some(Expression { operator: Operator::Or, operands: vec!(Operand::Test(1)) }),
+        });
+        // Passing alone, A needs nothing
+        assert_eq!(learner.observe(&[true, false, false]).unwrap()[0].status, Status::Independent);
+        assert!(learner.observe(&[true, true]).is_err());
+    }
+}
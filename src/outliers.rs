@@ -0,0 +1,143 @@
+/// Ways of recognizing an execution where the machine, not the code, was broken
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DetectionRule {
+    // At least this fraction of all tests failed
+    FailedFraction(f64),
+    // Disagreed with each test's usual outcome this many median-absolute-deviations more than normal
+    Deviation(f64),
+}
+
+impl DetectionRule {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DetectionRule::FailedFraction(_) => "failed-fraction",
+            DetectionRule::Deviation(_) => "deviation",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExecutionStats {
+    pub failed_fraction: f64,
+    pub disagreement: f64, // fraction of tests whose outcome differs from their usual (majority) outcome
+}
+
+/// `skipped` lists the tests that were skipped in each execution, which neither failed nor disagreed, and which don't
+/// count towards the tests an execution's failures are a fraction of
+pub fn execution_stats(results: &[&[bool]], skipped: &[Vec<usize>]) -> Vec<ExecutionStats> {
+    let test_count = results.len();
+    let executions = results[0].len();
+    let usually_passes: Vec<bool> = results.iter()
+        .map(|test| test.iter().filter(|&&passed| passed).count() * 2 >= executions)
+        .collect();
+
+    (0..executions)
+        .map(|execution_index| {
+            let ran = |&(test_id, _): &(usize, &&[bool])| {
+                !skipped.get(execution_index).is_some_and(|tests| tests.contains(&test_id))
+            };
+            let ran_count = results.iter().enumerate().filter(ran).count();
+            let failed = results.iter()
+                .enumerate()
+                .filter(ran)
+                .filter(|(_, test)| !test[execution_index])
+                .count();
+            let disagreeing = results.iter()
+                .enumerate()
+                .filter(ran)
+                .filter(|&(test_id, test)| test[execution_index] != usually_passes[test_id])
+                .count();
+
+            ExecutionStats {
+                failed_fraction: if ran_count == 0 { 0.0 } else { failed as f64 / ran_count as f64 },
+                disagreement: disagreeing as f64 / test_count as f64,
+            }
+        })
+        .collect()
+}
+
+/// Every execution flagged by any of the rules, paired with the first rule that flagged it
+pub fn detect_outliers(results: &[&[bool]], rules: &[DetectionRule], skipped: &[Vec<usize>])
+                       -> Vec<(usize, DetectionRule)> {
+    let stats = execution_stats(results, skipped);
+    let disagreements: Vec<f64> = stats.iter().map(|stat| stat.disagreement).collect();
+    let typical = median(&disagreements);
+    let spread = median(&disagreements.iter().map(|value| (value - typical).abs()).collect::<Vec<f64>>())
+        .max(1.0 / results.len() as f64); // Don't let a perfectly consistent history flag every deviation
+
+    stats.iter()
+        .enumerate()
+        .filter_map(|(execution_index, stat)| rules.iter()
+            .find(|rule| match **rule {
+                DetectionRule::FailedFraction(minimum) => stat.failed_fraction >= minimum,
+                DetectionRule::Deviation(deviations) => stat.disagreement - typical > deviations * spread,
+            })
+            .map(|&rule| (execution_index, rule)))
+        .collect()
+}
+
+fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 1 {
+        sorted[middle]
+    } else {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<Vec<bool>> {
+        vec!(
+            vec!(true, true, false, true, true, true),
+            vec!(true, false, false, true, true, true),
+            vec!(true, true, false, true, false, true),
+            vec!(false, true, false, true, true, true),
+        )
+    }
+
+    #[test]
+    fn should_calculate_failed_fraction_per_execution() {
+        let history = history();
+        let slices: Vec<&[bool]> = history.iter().map(|test| &test[..]).collect();
+        let stats = execution_stats(&slices, &[]);
+        assert_eq!(stats[0].failed_fraction, 0.25);
+        assert_eq!(stats[2], ExecutionStats { failed_fraction: 1.0, disagreement: 1.0 });
+    }
+
+    #[test]
+    fn should_only_count_tests_that_ran_in_the_failed_fraction() {
+        let history = history();
+        let slices: Vec<&[bool]> = history.iter().map(|test| &test[..]).collect();
+        let skipped = vec!(vec!(0, 2), vec!(), vec!(1, 2, 3), vec!(), vec!(0, 1, 2, 3));
+        let stats = execution_stats(&slices, &skipped);
+        assert_eq!(stats[0].failed_fraction, 0.5);
+        assert_eq!(stats[1].failed_fraction, 0.25);
+        assert_eq!(stats[2].failed_fraction, 1.0);
+        assert_eq!(stats[4].failed_fraction, 0.0);
+    }
+
+    #[test]
+    fn should_flag_executions_where_almost_everything_failed() {
+        let history = history();
+        let slices: Vec<&[bool]> = history.iter().map(|test| &test[..]).collect();
+        assert_eq!(detect_outliers(&slices, &[DetectionRule::FailedFraction(0.9)], &[]),
+                   vec!((2, DetectionRule::FailedFraction(0.9))));
+    }
+
+    #[test]
+    fn should_flag_executions_deviating_from_usual_outcomes() {
+        let history = history();
+        let slices: Vec<&[bool]> = history.iter().map(|test| &test[..]).collect();
+        assert_eq!(detect_outliers(&slices, &[DetectionRule::Deviation(2.0)], &[]),
+                   vec!((2, DetectionRule::Deviation(2.0))));
+    }
+}
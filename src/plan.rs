@@ -0,0 +1,163 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::{BTreeMap, BTreeSet};
+use check::Edge;
+use expression::*;
+use inference::TestModel;
+
+/// What a runner needs to run a suite in dependency order
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    pub order: Vec<usize>,
+    pub broken: Vec<Edge>, // Dependencies that run after their test, to break a cycle
+    pub skip_if: Vec<Option<Expression>>, // Per test, when the results so far mean it can't pass
+}
+
+/// Orders the tests so that every test runs after the tests its expression mentions, earliest in the input first. When
+/// every test left is waiting on another, the one waiting on the fewest (then the earliest) runs anyway, and the edges
+/// it jumps are reported as broken
+pub fn plan(models: &[TestModel]) -> Plan {
+    let mut waiting: Vec<BTreeSet<usize>> = models.iter()
+        .enumerate()
+        .map(|(test, model)| model.expression.iter()
+            .flat_map(|expression| expression.variables())
+            .map(|id| id as usize)
+            .filter(|&id| id != test && id < models.len())
+            .collect())
+        .collect();
+
+    let mut remaining: BTreeSet<usize> = (0..models.len()).collect();
+    let mut order = Vec::with_capacity(models.len());
+    let mut broken = Vec::new();
+    while !remaining.is_empty() {
+        let next = match remaining.iter().find(|&&test| waiting[test].is_empty()) {
+            Some(&test) => test,
+            None => {
+                let test = *remaining.iter().min_by_key(|&&test| (waiting[test].len(), test)).unwrap();
+                broken.extend(waiting[test].iter().map(|&dependency| Edge { test, dependency }));
+                test
+            },
+        };
+        remaining.remove(&next);
+        order.push(next);
+        for test in &remaining {
+            waiting[*test].remove(&next);
+        }
+    }
+
+    Plan {
+        order,
+        broken,
+        skip_if: models.iter().map(|model| model.expression.as_ref().map(negated)).collect(),
+    }
+}
+
+/// Whether the expression can still be satisfied, when only some of the results are known so far
+pub fn satisfiable(expression: &Expression, results: &[Option<bool>]) -> bool {
+    let possible = |operand: &Operand| match *operand {
+        Operand::Test(id) => results.get(id as usize) != Some(&Some(false)),
+        Operand::Not(id) => results.get(id as usize) != Some(&Some(true)),
+        Operand::Expression(ref inner) => satisfiable(inner, results),
+    };
+    match expression.operator {
+        Operator::Or => expression.operands.iter().any(possible),
+        Operator::And => expression.operands.iter().all(possible),
+        Operator::AtLeast(threshold) => {
+            expression.operands.iter().filter(|operand| possible(operand)).count() >= threshold
+        },
+    }
+}
+
+/// `{"order": [...], "broken": [{"test", "dependency"}], "skip_if": {test: operator}}`. Tests that are never skipped
+/// aren't in `skip_if`
+pub fn plan_json(plan: &Plan, lookup: &[String]) -> Json {
+    let mut map = BTreeMap::new();
+    map.insert("order".to_owned(), plan.order.iter()
+        .map(|&test| lookup[test].clone())
+        .collect::<Vec<String>>()
+        .to_json());
+    map.insert("broken".to_owned(), plan.broken.iter()
+        .map(|edge| {
+            let mut json = BTreeMap::new();
+            json.insert("test".to_owned(), lookup[edge.test].to_json());
+            json.insert("dependency".to_owned(), lookup[edge.dependency].to_json());
+            Json::Object(json)
+        })
+        .collect::<Vec<Json>>()
+        .to_json());
+    map.insert("skip_if".to_owned(), plan.skip_if.iter()
+        .enumerate()
+        .filter_map(|(test, skip_if)| skip_if.as_ref()
+            .map(|skip_if| (lookup[test].clone(), expression_json(skip_if, lookup))))
+        .collect::<BTreeMap<String, Json>>()
+        .to_json());
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evidence::*;
+
+    fn model(expression: Option<Expression>) -> TestModel {
+        TestModel {
+            status: if expression.is_some() { Status::DependsOn } else { Status::Independent },
+            evidence: Evidence {
+                passing_executions: 2,
+                passing_contexts: 2,
+                informative_contexts: 2,
+                independent: false,
+            },
+            expression,
+            alternatives: Vec::new(),
+            gap: None,
+            fallback: None,
+            violations: Vec::new(),
+        }
+    }
+
+    fn expression(operator: Operator, operands: Vec<Operand>) -> Option<Expression> {
+        Some(Expression { operator, operands })
+    }
+
+    #[test]
+    fn should_run_dependencies_first() {
+        // A needs B or C, C needs D, and D is independent
+        let models = vec!(
+            model(expression(Operator::Or, vec!(Operand::Test(1), Operand::Test(2)))),
+            model(None),
+            model(expression(Operator::Or, vec!(Operand::Test(3)))),
+            model(None),
+        );
+        let plan = plan(&models);
+        assert_eq!(plan.order, vec!(1, 3, 2, 0));
+        assert!(plan.broken.is_empty());
+        assert_eq!(plan.skip_if[0], expression(Operator::And, vec!(Operand::Not(1), Operand::Not(2))));
+        assert_eq!(plan.skip_if[1], None);
+    }
+
+    #[test]
+    fn should_break_cycles_the_same_way_every_time() {
+        // A needs B, B needs C, and C needs A or D, while D needs B
+        let models = vec!(
+            model(expression(Operator::Or, vec!(Operand::Test(1)))),
+            model(expression(Operator::Or, vec!(Operand::Test(2)))),
+            model(expression(Operator::Or, vec!(Operand::Test(0), Operand::Test(3)))),
+            model(expression(Operator::Or, vec!(Operand::Test(1)))),
+        );
+        let plan = plan(&models);
+        assert_eq!(plan.order, vec!(0, 1, 3, 2));
+        assert_eq!(plan.broken, vec!(Edge { test: 0, dependency: 1 }, Edge { test: 1, dependency: 2 }));
+    }
+
+    #[test]
+    fn should_only_skip_once_unsatisfiable() {
+        // 2 of (A, B, !C)
+        let two_of = Expression {
+            operator: Operator::AtLeast(2),
+            operands: vec!(Operand::Test(0), Operand::Test(1), Operand::Not(2)),
+        };
+        assert!(satisfiable(&two_of, &[Some(false), None, None]));
+        assert!(!satisfiable(&two_of, &[Some(false), None, Some(true)]));
+        assert!(satisfiable(&two_of, &[]));
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
+use budget::*;
 use expression::*;
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
@@ -34,7 +35,7 @@ struct QMStepRow {
     row: Vec<VariableState>,
     true_count: usize,
     used: bool,
-    covered_rows: Vec<usize>, //index of rows covered in original truth table
+    covered_rows: Vec<u64>, // minterms covered, as bitmasks of the variables that are true
 }
 
 impl PartialEq for QMStepRow {
@@ -87,9 +88,20 @@ impl QMStepRow {
     }
 }
 
-// Things got messy here. Operation "Get this project done" kicked into overdrive, and maintaining
-// a nice, testable structure became low-priority
-pub fn reduce(expression: &Expression) -> Option<Expression> {
+pub fn reduce(expression: &Expression) -> Result<Option<Expression>, BudgetExceeded> {
+    Ok(reduce_all(expression)?.into_iter().next())
+}
+
+/// Every minimum-cost (fewest implicants, then fewest literals) equivalent of the expression, in a stable order.
+/// Fails with more than `MAX_VARIABLES` variables
+pub fn reduce_all(expression: &Expression) -> Result<Vec<Expression>, BudgetExceeded> {
+    reduce_all_within(expression, &Budget::unlimited())
+}
+
+/// `reduce_all`, giving up once the budget runs out
+pub fn reduce_all_within(expression: &Expression, budget: &Budget) -> Result<Vec<Expression>, BudgetExceeded> {
+    // Things got messy here. Operation "Get this project done" kicked into overdrive, and maintaining
+    // a nice, testable structure became low-priority
     let mut variables = expression.variables();
     variables.sort(); // To maintain consitency with Turaco (for debugging)
     let mut max_variable: usize = 0;
@@ -110,35 +122,23 @@ pub fn reduce(expression: &Expression) -> Option<Expression> {
         variable_to_index[variable.clone() as usize] = i;
     }
 
-    let table = truth_table(expression, variables.len() as u32, &variable_to_index);
     let mut qm_steps = AllQMSteps::new(variables.len());
 
-    for i in 0..table.len() {
-        if !table[i][variables.len()] { // If the result (last cell) is "false", ignore row
-            continue;
-        }
-
-        let mut true_count = 0;
-        let mut row = Vec::<VariableState>::with_capacity(variables.len());
-
-        for j in 0..variables.len() {
-            row.push(match table[i][j] {
+    for minterm in minterms(expression, variables.len(), &variable_to_index, budget)? {
+        let minterm = minterm?;
+        let row: Vec<VariableState> = (0..variables.len())
+            .map(|j| match (minterm >> j) & 1 == 1 {
                 true => VariableState::True,
                 false => VariableState::False,
-            });
-            if table[i][j] {
-                true_count += 1;
-            }
-        }
-
-        let mut covered_rows = Vec::new();
-        covered_rows.push(i);
+            })
+            .collect();
+        let true_count = minterm.count_ones() as usize;
 
         qm_steps.steps[true_count].push(QMStepRow {
-            row: row,
-            true_count: true_count,
+            row,
+            true_count,
             used: false,
-            covered_rows: covered_rows,
+            covered_rows: vec!(minterm),
         })
     }
 
@@ -153,6 +153,7 @@ pub fn reduce(expression: &Expression) -> Option<Expression> {
                 }
 
                 for y in 0..qm_steps.steps[i + 1].len() {
+                    budget.step()?;
                     let new_step = qm_steps.steps[i][x].reduce(&(qm_steps.steps[i + 1][y]));
                     if new_step.is_none() {
                         continue;
@@ -185,69 +186,139 @@ pub fn reduce(expression: &Expression) -> Option<Expression> {
         );
     }
 
-    prime_implicants.dedup();
-    let minterms: HashSet<usize> =  prime_implicants.iter()
+    let mut unique_implicants = Vec::<QMStepRow>::with_capacity(prime_implicants.len());
+    for mut implicant in prime_implicants {
+        if !unique_implicants.contains(&implicant) {
+            implicant.covered_rows.sort();
+            implicant.covered_rows.dedup();
+            unique_implicants.push(implicant);
+        }
+    }
+    let prime_implicants = unique_implicants;
+
+    let minterms: BTreeSet<u64> = prime_implicants.iter()
         .flat_map(|qm_step_row| &qm_step_row.covered_rows)
         .cloned()
         .collect();
+    let covering = |minterm: &u64| -> Vec<usize> {
+        (0..prime_implicants.len())
+            .filter(|&i| prime_implicants[i].covered_rows.contains(minterm))
+            .collect()
+    };
 
-    let mut min_implicants = Vec::<QMStepRow>::new();
-    let mut remaining_minterms = minterms.clone();
-
+    // Essential prime implicants are the only ones covering some minterm, so every cover needs them
+    let mut essential = BTreeSet::<usize>::new();
     for minterm in &minterms {
-        let mut coverage = 0;
-        let mut index: usize = 0; // Guaranteed to be set in next for loop, if used
-        for i in 0..prime_implicants.len() {
-            if prime_implicants[i].covered_rows.contains(&minterm) {
-                coverage += 1;
-                index = i;
+        let implicants = covering(minterm);
+        if implicants.len() == 1 {
+            essential.insert(implicants[0]);
+        }
+    }
+
+    let remaining_minterms: Vec<u64> = minterms.iter()
+        .filter(|minterm| !essential.iter().any(|&i| prime_implicants[i].covered_rows.contains(minterm)))
+        .cloned()
+        .collect();
+
+    let cost = |cover: &BTreeSet<usize>| -> (usize, usize) {
+        let literals = cover.iter()
+            .map(|&i| prime_implicants[i].row.iter().filter(|state| **state != VariableState::Factored).count())
+            .sum();
+        (cover.len(), literals)
+    };
+
+    let mut covers: Vec<BTreeSet<usize>> = petrick(&remaining_minterms, &covering, budget)?.into_iter()
+        .map(|cover| cover.union(&essential).cloned().collect())
+        .collect();
+    let minimum_cost = covers.iter().map(&cost).min();
+    covers.retain(|cover| Some(cost(cover)) == minimum_cost);
+    covers.sort();
+
+    Ok(covers.iter()
+        .filter_map(|cover| {
+            let mut implicants: Vec<&QMStepRow> = cover.iter().map(|&i| &prime_implicants[i]).collect();
+            implicants.sort_by_key(|implicant| implicant_order(implicant));
+            implicants_expression(&implicants, &index_to_variable)
+        })
+        .collect())
+}
+
+/// Petrick's method: every irredundant choice of implicants that covers all of the minterms
+fn petrick<F: Fn(&u64) -> Vec<usize>>(minterms: &[u64], covering: &F, budget: &Budget)
+                                      -> Result<Vec<BTreeSet<usize>>, BudgetExceeded> {
+    let mut products = vec!(BTreeSet::<usize>::new());
+
+    for minterm in minterms {
+        let implicants = covering(minterm);
+        let mut next = Vec::<BTreeSet<usize>>::new();
+        for product in &products {
+            if implicants.iter().any(|i| product.contains(i)) {
+                next.push(product.clone());
+                continue;
+            }
+
+            for &i in &implicants {
+                budget.step()?;
+                let mut extended = product.clone();
+                extended.insert(i);
+                next.push(extended);
             }
         }
 
-        if coverage == 1 {
-            let implicant = prime_implicants.remove(index);
-            implicant.covered_rows.iter()
-                .fold((), |_, term| {
-                    remaining_minterms.remove(term);
-                });
-            min_implicants.push(implicant);
+        // X + XY = X: drop any product that's a superset of another
+        next.sort_by_key(|product| product.len());
+        products = Vec::new();
+        for product in next {
+            if !products.iter().any(|kept: &BTreeSet<usize>| kept.is_subset(&product)) {
+                products.push(product);
+            }
         }
     }
+    products.sort();
+    Ok(products)
+}
 
-    if remaining_minterms.len() != 0 {
-        // Usually "Petrick's Method" would be used here, but I don't think we'll ever reach
-        // the situation where it's necessary
-        unimplemented!();
-    }
+// Fewest literals first, then by which variables are used, so output doesn't depend on discovery order
+fn implicant_order(implicant: &QMStepRow) -> (usize, Vec<(usize, bool)>) {
+    let literals: Vec<(usize, bool)> = implicant.row.iter()
+        .enumerate()
+        .filter(|&(_, state)| *state != VariableState::Factored)
+        .map(|(i, state)| (i, *state == VariableState::False))
+        .collect();
+    (literals.len(), literals)
+}
 
+fn implicants_expression(implicants: &[&QMStepRow], index_to_variable: &[i32]) -> Option<Expression> {
     let mut root_expression = Expression {
         operator: Operator::Or,
         operands: vec!()
     };
 
-    let min_implicants_len = min_implicants.len();
-    for implicant in min_implicants {
-        if implicant.row.len() == 0 {
+    let min_implicants_len = implicants.len();
+    for implicant in implicants {
+        if implicant.row.is_empty() {
             continue;
         }
 
         let mut operands: Vec<Operand> = implicant.row.iter()
             .enumerate()
-            .map(|(i, state)| match *state {
+            .filter_map(|(i, state)| match *state {
                 VariableState::True => Some(Operand::Test(index_to_variable[i])),
-                VariableState::False => None,
+                VariableState::False => Some(Operand::Not(index_to_variable[i])),
                 VariableState::Factored => None,
             })
-            .filter(|option| option.is_some())
-            .map(|option| option.unwrap())
             .collect();
 
         operands.dedup();
 
+        if operands.is_empty() {
+            return None; // Every variable was factored out, so it's always true
+        }
+
         if min_implicants_len == 1 && operands.len() != 1 {
             return Some(Expression {
                 operator: Operator::And,
-                operands: operands,
+                operands,
             });
         }
 
@@ -256,7 +327,7 @@ pub fn reduce(expression: &Expression) -> Option<Expression> {
         } else if operands.len() > 1 {
             root_expression.operands.push(Operand::Expression(Expression {
                 operator: Operator::And,
-                operands: operands
+                operands
             }));
         }
     }
@@ -269,21 +340,187 @@ pub fn reduce(expression: &Expression) -> Option<Expression> {
     Some(root_expression)
 }
 
-pub fn truth_table(expression: &Expression, variable_count: u32, variable_to_index: &Vec<usize>) -> Vec<Vec<bool>> {
-    let case_length = (variable_count + 1) as usize; // One cell per variable, plus the result on the end
-    let case_count = (2 as i32).pow(variable_count as u32) as usize;
-    let mut table = Vec::<Vec<bool>>::with_capacity(case_count);
+/// The largest number of variables whose assignments still fit in a `u64` counter
+pub const MAX_VARIABLES: usize = 63;
+
+/// Lazily yields every assignment that satisfies the expression, as a bitmask where bit `i` is variable `i`. Every
+/// assignment tried is a step of the budget, so it fails straight away with more than `MAX_VARIABLES` variables, or
+/// more assignments than there are steps left
+pub fn minterms<'a>(expression: &'a Expression, variable_count: usize, variable_to_index: &'a [usize],
+                    budget: &'a Budget) -> Result<Minterms<'a>, BudgetExceeded> {
+    if variable_count > MAX_VARIABLES {
+        return Err(BudgetExceeded::Variables);
+    }
+    let last = (1u64 << variable_count) - 1;
+    if budget.remaining_steps().is_some_and(|steps| steps <= last) {
+        return Err(BudgetExceeded::Steps);
+    }
+    Ok(Minterms {
+        expression,
+        variable_to_index,
+        budget,
+        next: Some(0),
+        last,
+    })
+}
+
+pub struct Minterms<'a> {
+    expression: &'a Expression,
+    variable_to_index: &'a [usize],
+    budget: &'a Budget,
+    next: Option<u64>, // None once every assignment has been checked
+    last: u64,
+}
 
-    for case_index in 0..case_count {
-        let mut case = Vec::<bool>::with_capacity(case_length);
+impl<'a> Iterator for Minterms<'a> {
+    type Item = Result<u64, BudgetExceeded>;
 
-        for test_index in 0..(case_length - 1) {
-            case.push(((case_index >> test_index) & 1) == 1);
+    fn next(&mut self) -> Option<Result<u64, BudgetExceeded>> {
+        while let Some(case) = self.next {
+            self.next = if case == self.last { None } else { Some(case + 1) };
+            if let Err(reason) = self.budget.step() {
+                self.next = None;
+                return Some(Err(reason));
+            }
+            if self.expression.evaluate_mask(case, self.variable_to_index) {
+                return Some(Ok(case));
+            }
         }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
 
-        let result = expression.evaluate(&case, variable_to_index);
-        case.push(result);
-        table.push(case);
+    fn and(ids: &[i32]) -> Operand {
+        Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        })
     }
-    table
-}
\ No newline at end of file
+
+    #[test]
+    fn should_reduce_to_same_expression_every_time() {
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(and(&[1, 2]), and(&[2, 3]), and(&[3]), and(&[1, 2, 3]), and(&[1, 4]), and(&[4, 1, 5])),
+        };
+        let expected = Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(Operand::Test(3), and(&[1, 2]), and(&[1, 4])),
+        });
+
+        for _ in 0..10 {
+            assert_eq!(reduce(&expression), Ok(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn should_keep_tests_that_have_to_fail() {
+        // (A & !B) | (A & B & C) is A & (!B | C)
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(
+                Operand::Expression(Expression {
+                    operator: Operator::And,
+                    operands: vec!(Operand::Test(0), Operand::Not(1)),
+                }),
+                and(&[0, 1, 2]),
+            ),
+        };
+        assert_eq!(reduce(&expression), Ok(Some(Expression {
+            operator: Operator::Or,
+            operands: vec!(
+                Operand::Expression(Expression {
+                    operator: Operator::And,
+                    operands: vec!(Operand::Test(0), Operand::Not(1)),
+                }),
+                and(&[0, 2]),
+            ),
+        })));
+    }
+
+    #[test]
+    fn should_only_find_one_cover_for_dependencies() {
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2])),
+        };
+        assert_eq!(reduce_all(&expression).map(|covers| covers.len()), Ok(1));
+    }
+
+    #[test]
+    fn should_give_up_when_out_of_budget() {
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2])),
+        };
+        let budget = Budget::start(&BudgetLimits { time: None, steps: Some(2) });
+        assert_eq!(reduce_all_within(&expression, &budget), Err(BudgetExceeded::Steps));
+    }
+
+    #[test]
+    fn should_give_up_on_wide_expressions_without_trying_every_assignment() {
+        let expression = Expression {
+            operator: Operator::And,
+            operands: (0..30).map(Operand::Test).collect(),
+        };
+        let started = Instant::now();
+        let budget = Budget::start(&BudgetLimits { time: Some(Duration::from_millis(10)), steps: Some(100) });
+        assert_eq!(reduce_all_within(&expression, &budget), Err(BudgetExceeded::Steps));
+        let budget = Budget::start(&BudgetLimits { time: Some(Duration::from_millis(10)), steps: None });
+        assert_eq!(reduce_all_within(&expression, &budget), Err(BudgetExceeded::Time));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_only_yield_satisfying_assignments() {
+        let expression = Expression {
+            operator: Operator::Or,
+            operands: vec!(and(&[0, 1]), Operand::Test(2)),
+        };
+        let variable_to_index = vec!(0, 1, 2);
+        let budget = Budget::unlimited();
+        let all = minterms(&expression, 3, &variable_to_index, &budget).unwrap();
+        assert_eq!(all.collect::<Result<Vec<u64>, _>>(), Ok(vec!(0b011, 0b100, 0b101, 0b110, 0b111)));
+    }
+
+    #[test]
+    fn should_not_overflow_with_many_variables() {
+        let expression = Expression {
+            operator: Operator::And,
+            operands: (0..63).map(Operand::Test).collect(),
+        };
+        let variable_to_index: Vec<usize> = (0..63).collect();
+        let budget = Budget::unlimited();
+        let mut all = minterms(&expression, 63, &variable_to_index, &budget).unwrap();
+        all.next = Some(u64::MAX >> 1); // Skip straight to the only satisfying assignment
+        assert_eq!(all.collect::<Result<Vec<u64>, _>>(), Ok(vec!(u64::MAX >> 1)));
+    }
+
+    #[test]
+    fn should_refuse_more_variables_than_fit() {
+        let expression = Expression {
+            operator: Operator::And,
+            operands: (0..64).map(Operand::Test).collect(),
+        };
+        assert_eq!(reduce_all_within(&expression, &Budget::unlimited()), Err(BudgetExceeded::Variables));
+    }
+
+    #[test]
+    fn should_find_every_irredundant_cover() {
+        // Three minterms, each covered by two of three implicants in a cycle
+        let covering = |minterm: &u64| match *minterm {
+            0 => vec!(0, 1),
+            1 => vec!(1, 2),
+            _ => vec!(0, 2),
+        };
+        let covers: Vec<Vec<usize>> = petrick(&[0, 1, 2], &covering, &Budget::unlimited()).unwrap().iter()
+            .map(|cover| cover.iter().cloned().collect())
+            .collect();
+        assert_eq!(covers, vec!(vec!(0, 1), vec!(0, 2), vec!(1, 2)));
+    }
+}
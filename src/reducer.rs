@@ -0,0 +1,355 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use budget::*;
+use expression::*;
+use quine_mccluskey;
+use espresso::{self, Espresso};
+
+/// Minimizes a dependency expression. `None` means there's no dependency left at all
+pub trait Reducer {
+    fn name(&self) -> &'static str;
+
+    fn reduce(&self, expression: &Expression) -> Option<Expression>;
+
+    /// Every equally minimal result, for reducers that can tell. The first is always what `reduce` gives
+    fn reduce_all(&self, expression: &Expression) -> Vec<Expression> {
+        self.reduce(expression).into_iter().collect()
+    }
+
+    /// `reduce`, plus how many more AND terms the result may have than the smallest possible. Only heuristic
+    /// reducers report a gap, everything else is exact
+    fn reduce_bounded(&self, expression: &Expression) -> (Option<Expression>, Option<usize>) {
+        (self.reduce(expression), None)
+    }
+
+    /// `reduce_bounded`, giving up once the budget runs out. Reducers that are always quick ignore the budget
+    fn reduce_within(&self, expression: &Expression, _budget: &Budget)
+                     -> Result<(Option<Expression>, Option<usize>), BudgetExceeded> {
+        Ok(self.reduce_bounded(expression))
+    }
+
+    /// `reduce_all`, giving up once the budget runs out
+    fn reduce_all_within(&self, expression: &Expression, _budget: &Budget) -> Result<Vec<Expression>, BudgetExceeded> {
+        Ok(self.reduce_all(expression))
+    }
+}
+
+/// Exact minimization, exponential in the number of variables. Without a budget, expressions with more variables than
+/// it can enumerate at all get Espresso's result instead, as the fallbacks would give them
+pub struct QuineMcCluskey;
+
+impl Reducer for QuineMcCluskey {
+    fn name(&self) -> &'static str {
+        "qm"
+    }
+
+    fn reduce(&self, expression: &Expression) -> Option<Expression> {
+        self.reduce_all(expression).into_iter().next()
+    }
+
+    fn reduce_all(&self, expression: &Expression) -> Vec<Expression> {
+        quine_mccluskey::reduce_all(expression).unwrap_or_else(|_| {
+            Espresso { max_iterations: espresso::DEFAULT_ITERATIONS }.reduce(expression).into_iter().collect()
+        })
+    }
+
+    fn reduce_within(&self, expression: &Expression, budget: &Budget)
+                     -> Result<(Option<Expression>, Option<usize>), BudgetExceeded> {
+        Ok((self.reduce_all_within(expression, budget)?.into_iter().next(), None))
+    }
+
+    fn reduce_all_within(&self, expression: &Expression, budget: &Budget) -> Result<Vec<Expression>, BudgetExceeded> {
+        quine_mccluskey::reduce_all_within(expression, budget)
+    }
+}
+
+/// Drops repeated and absorbed (`X | (X & Y)` is just `X`) AND terms. For expressions without negation, which is
+/// everything that `dependency_expression` produces outside of negative mode, this is just as minimal as
+/// Quine-McCluskey. It can't merge `(A & B) | (A & !B)` into `A` though
+pub struct Absorption;
+
+impl Reducer for Absorption {
+    fn name(&self) -> &'static str {
+        "absorption"
+    }
+
+    fn reduce(&self, expression: &Expression) -> Option<Expression> {
+        let mut terms: Vec<BTreeSet<Literal>> = sum_of_products(expression).into_iter().collect();
+        terms.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+        let mut minimal = Vec::<BTreeSet<Literal>>::new();
+        for term in terms {
+            if !minimal.iter().any(|kept| kept.is_subset(&term)) {
+                minimal.push(term);
+            }
+        }
+
+        terms_expression(&minimal)
+    }
+}
+
+/// Past this many variables, Quine-McCluskey starts taking seconds per expression
+pub const DEFAULT_EXACT_VARIABLES: usize = 10;
+
+/// Quine-McCluskey when there are few enough variables for it to be fast, otherwise absorption, or Espresso if the
+/// expression has negated tests that absorption can't merge. It never picks Quine-McCluskey past
+/// `quine_mccluskey::MAX_VARIABLES`, however many `max_exact_variables` allows
+pub struct Auto {
+    pub max_exact_variables: usize,
+}
+
+impl Auto {
+    fn choose(&self, expression: &Expression) -> &'static dyn Reducer {
+        if expression.variables().len() <= self.max_exact_variables.min(quine_mccluskey::MAX_VARIABLES) {
+            &QuineMcCluskey
+        } else if expression.has_negation() {
+            &Espresso { max_iterations: espresso::DEFAULT_ITERATIONS }
+        } else {
+            &Absorption
+        }
+    }
+}
+
+impl Reducer for Auto {
+    fn name(&self) -> &'static str {
+        "auto"
+    }
+
+    fn reduce(&self, expression: &Expression) -> Option<Expression> {
+        self.choose(expression).reduce(expression)
+    }
+
+    fn reduce_all(&self, expression: &Expression) -> Vec<Expression> {
+        self.choose(expression).reduce_all(expression)
+    }
+
+    fn reduce_within(&self, expression: &Expression, budget: &Budget)
+                     -> Result<(Option<Expression>, Option<usize>), BudgetExceeded> {
+        self.choose(expression).reduce_within(expression, budget)
+    }
+
+    fn reduce_all_within(&self, expression: &Expression, budget: &Budget) -> Result<Vec<Expression>, BudgetExceeded> {
+        self.choose(expression).reduce_all_within(expression, budget)
+    }
+}
+
+/// Tuning for the built-in reducers
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReducerSettings {
+    pub exact_variables: usize, // Most variables `Auto` still reduces exactly
+    pub espresso_iterations: usize,
+}
+
+impl Default for ReducerSettings {
+    fn default() -> ReducerSettings {
+        ReducerSettings {
+            exact_variables: DEFAULT_EXACT_VARIABLES,
+            espresso_iterations: espresso::DEFAULT_ITERATIONS,
+        }
+    }
+}
+
+/// The built-in reducers, by name
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReducerKind {
+    QuineMcCluskey,
+    Absorption,
+    Espresso,
+    Auto,
+}
+
+impl ReducerKind {
+    pub fn build(&self, settings: &ReducerSettings) -> Box<dyn Reducer + Sync> {
+        match *self {
+            ReducerKind::QuineMcCluskey => Box::new(QuineMcCluskey),
+            ReducerKind::Absorption => Box::new(Absorption),
+            ReducerKind::Espresso => Box::new(Espresso { max_iterations: settings.espresso_iterations }),
+            ReducerKind::Auto => Box::new(Auto { max_exact_variables: settings.exact_variables }),
+        }
+    }
+
+    /// Cheaper reducers to try, in order, when this one runs out of budget. Absorption is always quick enough to be
+    /// the last resort, so it isn't listed
+    pub fn fallbacks(&self, settings: &ReducerSettings) -> Vec<Box<dyn Reducer + Sync>> {
+        match *self {
+            ReducerKind::QuineMcCluskey | ReducerKind::Auto => vec!(ReducerKind::Espresso.build(settings)),
+            ReducerKind::Espresso | ReducerKind::Absorption => vec!(),
+        }
+    }
+}
+
+impl FromStr for ReducerKind {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<ReducerKind, ()> {
+        match name {
+            "qm" => Ok(ReducerKind::QuineMcCluskey),
+            "absorption" => Ok(ReducerKind::Absorption),
+            "espresso" => Ok(ReducerKind::Espresso),
+            "auto" => Ok(ReducerKind::Auto),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Multiplies the expression out into a set of AND terms, each a set of literals. Terms that need a test to both
+/// pass and fail can never be true, so they're left out
+pub fn sum_of_products(expression: &Expression) -> BTreeSet<BTreeSet<Literal>> {
+    sum_of_products_within(expression, &Budget::unlimited()).unwrap_or_default()
+}
+
+/// `sum_of_products`, where every term multiplied out is a step of the budget, since factored and threshold
+/// expressions can grow exponentially
+pub fn sum_of_products_within(expression: &Expression, budget: &Budget)
+                              -> Result<BTreeSet<BTreeSet<Literal>>, BudgetExceeded> {
+    let mut terms = BTreeSet::new();
+    match expression.operator {
+        Operator::Or => for operand in &expression.operands {
+            terms.extend(operand_terms(operand, budget)?);
+        },
+        Operator::And => terms = products(&expression.operands.iter().collect::<Vec<&Operand>>(), budget)?,
+        // Any `threshold` of the operands all together
+        Operator::AtLeast(threshold) => for subset in subsets(expression.operands.len(), threshold) {
+            let operands: Vec<&Operand> = subset.iter().map(|&i| &expression.operands[i]).collect();
+            terms.extend(products(&operands, budget)?);
+        },
+    }
+    Ok(terms)
+}
+
+fn operand_terms(operand: &Operand, budget: &Budget) -> Result<BTreeSet<BTreeSet<Literal>>, BudgetExceeded> {
+    let literal_terms = |test: i32, negated: bool| -> BTreeSet<BTreeSet<Literal>> {
+        vec!(vec!(Literal { test, negated }).into_iter().collect()).into_iter().collect()
+    };
+    match *operand {
+        Operand::Test(id) => Ok(literal_terms(id, false)),
+        Operand::Not(id) => Ok(literal_terms(id, true)),
+        Operand::Expression(ref e) => sum_of_products_within(e, budget),
+    }
+}
+
+/// The AND of the operands, multiplied out
+fn products(operands: &[&Operand], budget: &Budget) -> Result<BTreeSet<BTreeSet<Literal>>, BudgetExceeded> {
+    let mut products: BTreeSet<BTreeSet<Literal>> = vec!(BTreeSet::new()).into_iter().collect();
+    for operand in operands {
+        let terms = operand_terms(operand, budget)?;
+        let mut next = BTreeSet::new();
+        for product in &products {
+            for term in &terms {
+                budget.step()?;
+                let product: BTreeSet<Literal> = product.union(term).cloned().collect();
+                let contradictory = product.iter()
+                    .any(|literal| product.contains(&Literal { test: literal.test, negated: !literal.negated }));
+                if !contradictory {
+                    next.insert(product);
+                }
+            }
+        }
+        products = next;
+    }
+    Ok(products)
+}
+
+/// Every way of picking `size` of the indices below `count`, each in increasing order
+fn subsets(count: usize, size: usize) -> Vec<Vec<usize>> {
+    if size == 0 {
+        return vec!(vec!());
+    }
+    if size > count {
+        return vec!();
+    }
+
+    let mut with_last: Vec<Vec<usize>> = subsets(count - 1, size - 1);
+    for subset in with_last.iter_mut() {
+        subset.push(count - 1);
+    }
+    let mut all = subsets(count - 1, size);
+    all.extend(with_last);
+    all
+}
+
+/// Builds the same shape of expression as Quine-McCluskey does: a bare AND for one term, otherwise an OR
+pub fn terms_expression(terms: &[BTreeSet<Literal>]) -> Option<Expression> {
+    if terms.is_empty() || terms.iter().any(|term| term.is_empty()) {
+        return None; // Never true, or always true
+    }
+
+    if terms.len() == 1 && terms[0].len() > 1 {
+        return Some(Expression {
+            operator: Operator::And,
+            operands: terms[0].iter().map(Literal::operand).collect(),
+        });
+    }
+
+    Some(Expression {
+        operator: Operator::Or,
+        operands: terms.iter()
+            .map(|term| if term.len() == 1 {
+                term.iter().next().unwrap().operand()
+            } else {
+                Operand::Expression(Expression {
+                    operator: Operator::And,
+                    operands: term.iter().map(Literal::operand).collect(),
+                })
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn and(ids: &[i32]) -> Operand {
+        Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        })
+    }
+
+    fn or(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::Or, operands }
+    }
+
+    #[test]
+    fn should_absorb_like_quine_mccluskey() {
+        let expressions = vec!(
+            or(vec!(and(&[1, 2]), and(&[2, 3]), and(&[3]), and(&[1, 2, 3]), and(&[1, 4]), and(&[4, 1, 5]))),
+            or(vec!(and(&[0, 2]), and(&[0, 2, 3]))),
+            or(vec!(and(&[0]))),
+            or(vec!(and(&[0, 1]), and(&[1, 2]), and(&[0, 2]))),
+        );
+
+        for expression in &expressions {
+            assert_eq!(Absorption.reduce(expression), QuineMcCluskey.reduce(expression));
+        }
+    }
+
+    #[test]
+    fn should_multiply_out_nested_expressions() {
+        let expression = Expression {
+            operator: Operator::And,
+            operands: vec!(Operand::Test(0), Operand::Expression(or(vec!(Operand::Test(1), Operand::Test(2))))),
+        };
+        assert_eq!(Absorption.reduce(&expression), Some(or(vec!(and(&[0, 1]), and(&[0, 2])))));
+    }
+
+    #[test]
+    fn should_pick_exact_reducer_for_few_variables() {
+        let expression = or(vec!(and(&[0, 1, 2])));
+        assert_eq!(Auto { max_exact_variables: 3 }.choose(&expression).name(), "qm");
+        assert_eq!(Auto { max_exact_variables: 2 }.choose(&expression).name(), "absorption");
+
+        let wide = Expression { operator: Operator::And, operands: (0..64).map(Operand::Test).collect() };
+        assert_eq!(Auto { max_exact_variables: 100 }.choose(&wide).name(), "absorption");
+        assert_eq!(QuineMcCluskey.reduce(&wide), Absorption.reduce(&wide));
+    }
+
+    #[test]
+    fn should_look_up_reducers_by_name() {
+        assert_eq!("qm".parse(), Ok(ReducerKind::QuineMcCluskey));
+        assert_eq!("auto".parse::<ReducerKind>().unwrap().build(&ReducerSettings::default()).name(), "auto");
+        assert_eq!("espresso".parse::<ReducerKind>().unwrap().build(&ReducerSettings::default()).name(), "espresso");
+        assert_eq!("nope".parse::<ReducerKind>(), Err(()));
+    }
+}
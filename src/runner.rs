@@ -0,0 +1,140 @@
+use rustc_serialize::json::{ToJson, Json};
+use std::collections::BTreeMap;
+use evidence::Status;
+use inference::TestModel;
+use plan::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Skipped, // Not run, because its dependency could no longer pass
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped-dependency",
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Passed
+    }
+}
+
+/// Reads `{"test name": "shell command", ...}`
+pub fn parse_commands(text: &str) -> Result<Vec<(String, String)>, String> {
+    let json = Json::from_str(text).map_err(|e| e.to_string())?;
+    let object = json.as_object().ok_or_else(|| "Expected an object of test names to commands".to_owned())?;
+    object.iter()
+        .map(|(name, command)| command.as_string()
+            .map(|command| (name.clone(), command.to_owned()))
+            .ok_or_else(|| format!("The command for \"{}\" isn't a string", name)))
+        .collect()
+}
+
+/// Runs every command, in the planned order for the tests in the history and then the new ones. A test is skipped once
+/// its dependency can't be satisfied by the results so far, and a skipped test counts as failed for the tests after it.
+/// `execute` runs one test's command, and says whether it passed
+pub fn run_suite<F>(names: &[String], models: &[TestModel], commands: &[(String, String)], mut execute: F)
+    -> Result<Vec<(String, Outcome)>, String> where F: FnMut(&str, &str) -> bool {
+    let mut order = Vec::<(Option<usize>, &(String, String))>::with_capacity(commands.len());
+    for test in plan(models).order {
+        let command = commands.iter()
+            .find(|(name, _)| *name == names[test])
+            .ok_or_else(|| format!("No command for \"{}\"", names[test]))?;
+        order.push((Some(test), command));
+    }
+    order.extend(commands.iter()
+        .filter(|(name, _)| !names.contains(name))
+        .map(|command| (None, command)));
+
+    let mut results = vec!(None; names.len());
+    let mut outcomes = Vec::with_capacity(order.len());
+    for (test, (name, command)) in order {
+        let unsatisfiable = test
+            .and_then(|test| match (models[test].status, &models[test].expression) {
+                (Status::DependsOn, Some(expression)) => Some(!satisfiable(expression, &results)),
+                _ => None,
+            })
+            .unwrap_or(false);
+        let outcome = if unsatisfiable {
+            Outcome::Skipped
+        } else if execute(name, command) {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        };
+        if let Some(test) = test {
+            results[test] = Some(outcome.passed());
+        }
+        outcomes.push((name.clone(), outcome));
+    }
+    Ok(outcomes)
+}
+
+/// `[{"test", "outcome"}]`, in the order that the tests ran
+pub fn outcomes_json(outcomes: &[(String, Outcome)]) -> Json {
+    outcomes.iter()
+        .map(|&(ref name, outcome)| {
+            let mut map = BTreeMap::new();
+            map.insert("test".to_owned(), name.to_json());
+            map.insert("outcome".to_owned(), outcome.name().to_json());
+            Json::Object(map)
+        })
+        .collect::<Vec<Json>>()
+        .to_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use execution_matrix::ExecutionMatrix;
+    use inference::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn should_skip_tests_that_can_no_longer_pass() {
+        // Horn needs Battery and Wiring, and Battery needs Wiring. Paint and Wiring are independent
+        let slice: &[&[bool]] = &[
+            &[true, true, false, false, false],
+            &[true, false, false, false, false],
+            &[false, true, true, true, false],
+            &[true, true, true, false, true],
+        ];
+        let names = strings(&["Battery", "Horn", "Paint", "Wiring"]);
+        let models = infer_all(&ExecutionMatrix::new(slice), &InferenceOptions::default(), 1);
+        let commands = r#"{"Battery": "true", "Horn": "true", "Lights": "true", "Paint": "true", "Wiring": "false"}"#;
+        let commands = parse_commands(commands).unwrap();
+
+        let mut ran = Vec::new();
+        let outcomes = run_suite(&names, &models, &commands, |name, command| {
+            ran.push(name.to_owned());
+            command == "true"
+        }).unwrap();
+        assert_eq!(ran, strings(&["Paint", "Wiring", "Lights"]));
+        assert_eq!(outcomes, vec!(
+            ("Paint".to_owned(), Outcome::Passed),
+            ("Wiring".to_owned(), Outcome::Failed),
+            ("Battery".to_owned(), Outcome::Skipped),
+            ("Horn".to_owned(), Outcome::Skipped),
+            ("Lights".to_owned(), Outcome::Passed),
+        ));
+    }
+
+    #[test]
+    fn should_need_a_command_for_every_test_in_the_history() {
+        let slice: &[&[bool]] = &[&[true], &[true]];
+        let models = infer_all(&ExecutionMatrix::new(slice), &InferenceOptions::default(), 1);
+        let commands = parse_commands(r#"{"A": "true"}"#).unwrap();
+        assert_eq!(run_suite(&strings(&["A", "B"]), &models, &commands, |_, _| true),
+                   Err("No command for \"B\"".to_owned()));
+        assert!(parse_commands(r#"{"A": 1}"#).is_err());
+    }
+}
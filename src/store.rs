@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use bitset::BitVector;
+use test_results::*;
+
+/// Starts every history store, followed by the format version
+pub const MAGIC: &[u8; 4] = b"CDTH";
+const VERSION: u8 = 1;
+
+const TEST_RECORD: u8 = 1; // A test name, numbered in the order they appear
+const EXECUTION_RECORD: u8 = 2; // An execution's id, then one bit per test known at the time
+const METADATA_RECORD: u8 = 3; // A key and value, labelling the execution before it
+
+/// One stored execution. Tests added to the store after it count as having failed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StoredExecution {
+    pub id: Option<String>,
+    pub passed: BitVector,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The whole history, as an append-only file of records. Adding an execution only writes that execution (and any
+/// new test names) to the end of the file
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct HistoryStore {
+    pub names: Vec<String>,
+    pub executions: Vec<StoredExecution>,
+}
+
+impl HistoryStore {
+    /// Whether the file is a history store rather than a CSV history
+    pub fn is_store(path: &str) -> bool {
+        let mut magic = [0; 4];
+        File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == *MAGIC
+    }
+
+    /// An empty store if the file doesn't exist yet
+    pub fn read(path: &str) -> Result<HistoryStore, String> {
+        if !Path::new(path).exists() {
+            return Ok(HistoryStore::default());
+        }
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| e.to_string())?;
+        HistoryStore::decode(&bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<HistoryStore, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(4)? != MAGIC || reader.u8()? != VERSION {
+            return Err("Not a history store".to_owned());
+        }
+
+        let mut store = HistoryStore::default();
+        while !reader.is_done() {
+            match reader.u8()? {
+                TEST_RECORD => store.names.push(reader.string()?),
+                EXECUTION_RECORD => {
+                    let id = reader.string()?;
+                    let count = reader.u32()? as usize;
+                    if count > store.names.len() {
+                        return Err(format!("An execution has {} tests, but only {} are known", count,
+                                           store.names.len()));
+                    }
+                    let bits = reader.bytes(count.div_ceil(8))?;
+                    let mut passed = BitVector::new(count);
+                    for test in 0..count {
+                        passed.set(test, bits[test / 8] & (1 << (test % 8)) != 0);
+                    }
+                    let id = if id.is_empty() { None } else { Some(id) };
+                    store.executions.push(StoredExecution { id, passed, metadata: BTreeMap::new() });
+                },
+                METADATA_RECORD => {
+                    let key = reader.string()?;
+                    let value = reader.string()?;
+                    store.executions.last_mut()
+                        .ok_or_else(|| "Metadata before any execution".to_owned())?
+                        .metadata.insert(key, value);
+                },
+                record => return Err(format!("Unknown record {}", record)),
+            }
+        }
+        Ok(store)
+    }
+
+    /// The records that add an execution with these results, including any tests that are new to the store
+    pub fn execution_records(&self, results: &[(String, bool)], id: Option<&str>,
+                             metadata: &BTreeMap<String, String>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut names = self.names.clone();
+        for (name, _) in results {
+            if !names.contains(name) {
+                bytes.push(TEST_RECORD);
+                put_string(&mut bytes, name);
+                names.push(name.clone());
+            }
+        }
+
+        let mut bits = vec!(0u8; names.len().div_ceil(8));
+        for (test, name) in names.iter().enumerate() {
+            if passed_in(results, name) {
+                bits[test / 8] |= 1 << (test % 8);
+            }
+        }
+        bytes.push(EXECUTION_RECORD);
+        put_string(&mut bytes, id.unwrap_or(""));
+        put_u32(&mut bytes, names.len() as u32);
+        bytes.extend(bits);
+        for (key, value) in metadata.iter().filter(|(_, value)| !value.is_empty()) {
+            bytes.push(METADATA_RECORD);
+            put_string(&mut bytes, key);
+            put_string(&mut bytes, value);
+        }
+        bytes
+    }
+
+    /// Adds an execution in memory only, as `execution_records` would add it to the file
+    pub fn push(&mut self, results: &[(String, bool)], id: Option<&str>, metadata: &BTreeMap<String, String>) {
+        for (name, _) in results {
+            if !self.names.contains(name) {
+                self.names.push(name.clone());
+            }
+        }
+        let passed: Vec<bool> = self.names.iter().map(|name| passed_in(results, name)).collect();
+        let id = id.filter(|id| !id.is_empty()).map(String::from);
+        let metadata = metadata.iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        self.executions.push(StoredExecution { id, passed: BitVector::from_bools(&passed), metadata });
+    }
+
+    /// Adds an execution to the end of the store's file, creating it if it doesn't exist yet
+    pub fn append(&mut self, path: &str, results: &[(String, bool)], id: Option<&str>,
+                  metadata: &BTreeMap<String, String>) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        if !Path::new(path).exists() {
+            bytes.extend(MAGIC.iter());
+            bytes.push(VERSION);
+        }
+        bytes.extend(self.execution_records(results, id, metadata));
+        OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| file.write_all(&bytes))
+            .map_err(|e| e.to_string())?;
+        self.push(results, id, metadata);
+        Ok(())
+    }
+
+    /// A whole store with every execution of the results, in order
+    pub fn encode_results(results: &AllTestResults) -> Vec<u8> {
+        let mut store = HistoryStore::default();
+        let mut bytes = Vec::from(&MAGIC[..]);
+        bytes.push(VERSION);
+        for execution_index in 0..results.execution_count() {
+            let execution: Vec<(String, bool)> = results.results.iter()
+                .map(|test| (test.name.clone(), test.executions[execution_index]))
+                .collect();
+            let id = results.execution_id(execution_index);
+            let metadata = results.execution_metadata(execution_index);
+            let metadata: BTreeMap<String, String> = metadata.into_iter().filter(|(key, _)| key != "id").collect();
+            bytes.extend(store.execution_records(&execution, id, &metadata));
+            store.push(&execution, id, &metadata);
+        }
+        bytes
+    }
+
+    /// The executions that the predicate picks, as if they were the whole history
+    pub fn select<P>(&self, predicate: P) -> AllTestResults where P: Fn(usize, &StoredExecution) -> bool {
+        let selected: Vec<&StoredExecution> = self.executions.iter()
+            .enumerate()
+            .filter(|&(execution_index, execution)| predicate(execution_index, execution))
+            .map(|(_, execution)| execution)
+            .collect();
+        let tests = self.names.iter()
+            .enumerate()
+            .map(|(test, name)| Test::new(test as i32, name.clone(), selected.iter()
+                .map(|execution| test < execution.passed.len() && execution.passed.get(test))
+                .collect()))
+            .collect();
+
+        let mut results = AllTestResults::new(tests);
+        if selected.iter().any(|execution| execution.id.is_some()) {
+            results.execution_ids = selected.iter()
+                .map(|execution| execution.id.clone().unwrap_or_default())
+                .collect();
+        }
+        let keys: BTreeSet<&String> = selected.iter().flat_map(|execution| execution.metadata.keys()).collect();
+        results.with_metadata(keys.into_iter()
+            .map(|key| (key.clone(), selected.iter()
+                .map(|execution| execution.metadata.get(key).cloned().unwrap_or_default())
+                .collect()))
+            .collect())
+    }
+
+    /// The executions in the range of indexes
+    pub fn results(&self, range: Range<usize>) -> AllTestResults {
+        self.select(|execution_index, _| range.contains(&execution_index))
+    }
+}
+
+fn passed_in(results: &[(String, bool)], name: &str) -> bool {
+    results.iter().any(|&(ref test, passed)| test == name && passed)
+}
+
+pub fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend(value.to_le_bytes().iter());
+}
+
+pub fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend(value.to_le_bytes().iter());
+}
+
+pub fn put_string(bytes: &mut Vec<u8>, value: &str) {
+    put_u32(bytes, value.len() as u32);
+    bytes.extend(value.as_bytes());
+}
+
+/// Reads back what the `put_` functions wrote, failing instead of reading past the end
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < count {
+            return Err("Unexpected end of file".to_owned());
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let mut value = [0; 4];
+        value.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(value))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn should_read_back_what_was_stored() {
+        let csv = "@id,run-1,run-2,run-3\n@commit,a1f,,c07\nA,1,0,1\nB,0,1,1\n";
+        let results = parse_results(BufReader::new(csv.as_bytes())).unwrap();
+        let store = HistoryStore::decode(&HistoryStore::encode_results(&results)).unwrap();
+        assert_eq!(store.results(0..3), results);
+        assert_eq!(store.results(1..3).to_csv(), "@id,run-2,run-3\n@commit,,c07\nA,0,1\nB,1,1\n");
+        assert_eq!(store.select(|_, execution| execution.id == Some("run-2".to_owned())).to_csv(),
+                   "@id,run-2\nA,0\nB,1\n");
+        assert_eq!(store.executions[2].metadata.get("commit").map(String::as_str), Some("c07"));
+    }
+
+    #[test]
+    fn should_add_tests_as_they_appear() {
+        let mut store = HistoryStore::default();
+        let mut bytes = Vec::from(&MAGIC[..]);
+        bytes.push(VERSION);
+        for results in [vec!(("A".to_owned(), true)), vec!(("B".to_owned(), true), ("A".to_owned(), false))] {
+            bytes.extend(store.execution_records(&results, None, &BTreeMap::new()));
+            store.push(&results, None, &BTreeMap::new());
+        }
+        assert_eq!(HistoryStore::decode(&bytes), Ok(store));
+
+        let store = HistoryStore::decode(&bytes).unwrap();
+        assert_eq!(store.results(0..2).to_csv(), "A,1,0\nB,0,1\n");
+        assert!(HistoryStore::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(HistoryStore::decode(b"A,1,0").is_err());
+    }
+}
@@ -1,8 +1,9 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
 use std::io::{BufReader, BufRead, Read};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Test {
     pub id: i32,
     pub name: String,
@@ -19,12 +20,204 @@ impl Test {
 pub struct AllTestResults {
     pub results: Vec<Test>,
     pub count: usize,
+    pub execution_ids: Vec<String>, // Empty unless the history has an "@id" row
+    pub metadata: BTreeMap<String, Vec<String>>, // Other "@key" rows, such as "@commit" or "@timestamp", by key
 }
 
 impl AllTestResults {
     pub fn new(results: Vec<Test>) -> AllTestResults {
         let count = results.len();
-        AllTestResults{ results: results, count: count }
+        AllTestResults{ results: results, count: count, execution_ids: Vec::new(), metadata: BTreeMap::new() }
+    }
+
+    pub fn slices(&self) -> Vec<&[bool]> {
+        self.results.iter()
+            .map(|test| &test.executions[..])
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.results.iter()
+            .map(|test| test.name.clone())
+            .collect()
+    }
+
+    pub fn execution_count(&self) -> usize {
+        self.results.first().map_or(0, |test| test.executions.len())
+    }
+
+    pub fn execution_id(&self, execution_index: usize) -> Option<&str> {
+        self.execution_ids.get(execution_index).map(|id| id.as_str())
+    }
+
+    /// The execution's value for a metadata key, if it has one
+    pub fn metadata_value(&self, key: &str, execution_index: usize) -> Option<&str> {
+        self.metadata.get(key)
+            .and_then(|values| values.get(execution_index))
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Every metadata value of the execution, including its id, without the empty ones
+    pub fn execution_metadata(&self, execution_index: usize) -> BTreeMap<String, String> {
+        let mut metadata: BTreeMap<String, String> = self.metadata.keys()
+            .filter_map(|key| self.metadata_value(key, execution_index).map(|value| (key.clone(), value.to_owned())))
+            .collect();
+        if let Some(id) = self.execution_id(execution_index).filter(|id| !id.is_empty()) {
+            metadata.insert("id".to_owned(), id.to_owned());
+        }
+        metadata
+    }
+
+    /// For each execution, the tests that `run` skipped because their dependency couldn't pass, from the "skipped"
+    /// metadata of test names separated by ";". They're recorded as failed, but never actually ran
+    pub fn skipped_tests(&self) -> Vec<Vec<usize>> {
+        (0..self.execution_count())
+            .map(|execution_index| self.metadata_value("skipped", execution_index)
+                .map_or_else(Vec::new, |names| names.split(';')
+                    .filter_map(|name| self.results.iter().position(|test| test.name == name))
+                    .collect()))
+            .collect()
+    }
+
+    /// Drops the given tests entirely, renumbering the remaining ones
+    pub fn without_tests(&self, excluded: &[usize]) -> AllTestResults {
+        AllTestResults::new(self.results.iter()
+            .enumerate()
+            .filter(|&(index, _)| !excluded.contains(&index))
+            .map(|(_, test)| test)
+            .enumerate()
+            .map(|(id, test)| Test::new(id as i32, test.name.clone(), test.executions.clone()))
+            .collect())
+            .with_execution_ids(self.execution_ids.clone())
+            .with_metadata(self.metadata.clone())
+    }
+
+    /// Drops the given execution columns from every test
+    pub fn without_executions(&self, excluded: &[usize]) -> AllTestResults {
+        let keep = |index: usize| !excluded.contains(&index);
+        AllTestResults::new(self.results.iter()
+            .map(|test| Test::new(test.id, test.name.clone(), test.executions.iter()
+                .enumerate()
+                .filter(|&(index, _)| keep(index))
+                .map(|(_, &passed)| passed)
+                .collect()))
+            .collect())
+            .with_execution_ids(self.execution_ids.iter()
+                .enumerate()
+                .filter(|&(index, _)| keep(index))
+                .map(|(_, id)| id.clone())
+                .collect())
+            .with_metadata(self.metadata.iter()
+                .map(|(key, values)| (key.clone(), values.iter()
+                    .enumerate()
+                    .filter(|&(index, _)| keep(index))
+                    .map(|(_, value)| value.clone())
+                    .collect()))
+                .collect())
+    }
+
+    /// Adds one execution column, where tests missing from `results` failed. Tests that are new to the history are
+    /// added as having failed every earlier execution, and an id or metadata keys that are new to it as empty values
+    /// for every earlier execution
+    pub fn with_execution(&self, results: &[(String, bool)], id: Option<String>,
+                          metadata: &BTreeMap<String, String>) -> AllTestResults {
+        let count = self.execution_count();
+        let passed = |name: &str| results.iter().any(|&(ref test, passed)| test == name && passed);
+        let mut tests: Vec<Test> = self.results.iter()
+            .map(|test| {
+                let mut executions = test.executions.clone();
+                executions.push(passed(&test.name));
+                Test::new(test.id, test.name.clone(), executions)
+            })
+            .collect();
+        for (name, _) in results {
+            if !tests.iter().any(|test| test.name == *name) {
+                let mut executions = vec!(false; count);
+                executions.push(passed(name));
+                tests.push(Test::new(tests.len() as i32, name.clone(), executions));
+            }
+        }
+
+        let extend = |values: Option<&Vec<String>>, value: Option<&String>| {
+            let mut values = values.cloned().unwrap_or_else(|| vec!(String::new(); count));
+            values.push(value.cloned().unwrap_or_default());
+            values
+        };
+        let execution_ids = match (self.execution_ids.is_empty(), id) {
+            (true, None) => Vec::new(),
+            (true, Some(id)) => extend(None, Some(&id)),
+            (false, id) => extend(Some(&self.execution_ids), id.as_ref()),
+        };
+        let metadata = self.metadata.keys()
+            .chain(metadata.keys())
+            .map(|key| (key.clone(), extend(self.metadata.get(key), metadata.get(key))))
+            .collect();
+        AllTestResults::new(tests).with_execution_ids(execution_ids).with_metadata(metadata)
+    }
+
+    /// Writes the history back out in the format that `parse_results` reads
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        if !self.execution_ids.is_empty() {
+            csv.push_str(&format!("@id,{}\n", self.execution_ids.join(",")));
+        }
+        for (key, values) in &self.metadata {
+            csv.push_str(&format!("@{},{}\n", key, values.join(",")));
+        }
+        for test in &self.results {
+            let executions: Vec<&str> = test.executions.iter()
+                .map(|&passed| if passed { "1" } else { "0" })
+                .collect();
+            csv.push_str(&format!("{},{}\n", test.name, executions.join(",")));
+        }
+        csv
+    }
+
+    /// Only the executions whose metadata the filter matches
+    pub fn matching(&self, filter: &ExecutionFilter) -> AllTestResults {
+        if filter.is_empty() {
+            return self.without_executions(&[]);
+        }
+        self.without_executions(&(0..self.execution_count())
+            .filter(|&execution_index| !filter.matches(&self.execution_metadata(execution_index)))
+            .collect::<Vec<usize>>())
+    }
+
+    fn with_execution_ids(mut self, execution_ids: Vec<String>) -> AllTestResults {
+        self.execution_ids = execution_ids;
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, Vec<String>>) -> AllTestResults {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Which executions to keep, by their metadata. Timestamps compare as text, so they should be written the same way
+/// throughout, e.g. "2026-09-01T10:00:00Z"
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ExecutionFilter {
+    pub labels: Vec<(String, String)>, // Every key must have this value
+    pub since: Option<String>, // The earliest timestamp kept
+    pub until: Option<String>, // The latest timestamp kept, where "2026-09-30" keeps all of that day
+}
+
+impl ExecutionFilter {
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.since.is_none() && self.until.is_none()
+    }
+
+    pub fn matches(&self, metadata: &BTreeMap<String, String>) -> bool {
+        let timestamp = metadata.get("timestamp").map_or("", |timestamp| timestamp.as_str());
+        self.labels.iter().all(|(key, value)| metadata.get(key) == Some(value))
+            && self.since.as_ref().is_none_or(|since| !timestamp.is_empty() && timestamp >= since.as_str())
+            && self.until.as_ref().is_none_or(|until| {
+                // By bytes, since the prefix could end inside a multi-byte character
+                let prefix = &timestamp.as_bytes()[..until.len().min(timestamp.len())];
+                !timestamp.is_empty() && prefix <= until.as_bytes()
+            })
     }
 }
 
@@ -39,16 +232,19 @@ impl<'a> CsvTestSource<'a> {
 
     pub fn read_tests(&self) -> Result<AllTestResults, String> {
         let file = try!(File::open(self.filename).map_err(|e| e.to_string()));
-        let result = parse(BufReader::new(file));
-        Ok(AllTestResults::new(result.unwrap()))
+        parse_results(BufReader::new(file)).map_err(|e| format!("{:?}", e))
     }
 }
 
-pub fn parse<T: Read>(reader: BufReader<T>) -> Result<Vec<Test>, ParseError> {
+/// Reads one test per row, plus the optional "@id" row, which names each execution column, and any other "@key" rows,
+/// which label each execution with a value such as its commit or timestamp
+pub fn parse_results<T: Read>(reader: BufReader<T>) -> Result<AllTestResults, ParseError> {
     let mut all_results: Vec<Test> = Vec::new();
+    let mut execution_ids = Vec::<String>::new();
+    let mut metadata = BTreeMap::<String, Vec<String>>::new();
     let mut next_test_id = 0;
     for line in reader.lines() {
-        let line = try!(line);
+        let line = line?;
 
         let values: Vec<String> = line.split(',')
             .map(|value| value.to_owned())
@@ -58,6 +254,22 @@ pub fn parse<T: Read>(reader: BufReader<T>) -> Result<Vec<Test>, ParseError> {
             continue;
         }
 
+        if values[0].trim() == "@id" {
+            execution_ids = values.iter()
+                .skip(1)
+                .map(|value| value.trim().to_owned())
+                .collect();
+            continue;
+        }
+
+        if values[0].trim().starts_with('@') {
+            metadata.insert(values[0].trim()[1..].to_owned(), values.iter()
+                .skip(1)
+                .map(|value| value.trim().to_owned())
+                .collect());
+            continue;
+        }
+
         if !values.iter()
             .skip(1)
             .map(|value| value.trim())
@@ -77,9 +289,54 @@ pub fn parse<T: Read>(reader: BufReader<T>) -> Result<Vec<Test>, ParseError> {
 
     if all_results.len() == 0 {
         Err(ParseError::NoTestExecutions)
+    } else if (!execution_ids.is_empty() && execution_ids.len() != all_results[0].executions.len())
+        || metadata.values().any(|values| values.len() != all_results[0].executions.len()) {
+        Err(ParseError::InvalidFormat)
     } else {
-        Ok(all_results)
+        Ok(AllTestResults::new(all_results).with_execution_ids(execution_ids).with_metadata(metadata))
+    }
+}
+
+/// Reads metadata kept beside the history, as a CSV whose header is "index" or "id" and then the keys, e.g.
+/// "id,commit,branch". Each row labels the execution with that index or id, and replaces any values it already had
+pub fn parse_metadata<T: Read>(reader: BufReader<T>, results: &AllTestResults) -> Result<AllTestResults, ParseError> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(line) => line?,
+        None => return Ok(results.without_executions(&[])),
+    };
+    let header: Vec<String> = header.split(',').map(|key| key.trim().to_owned()).collect();
+    let by_id = match header[0].as_str() {
+        "index" => false,
+        "id" => true,
+        _ => return Err(ParseError::InvalidFormat),
+    };
+
+    let count = results.execution_count();
+    let mut metadata = results.metadata.clone();
+    for key in header.iter().skip(1) {
+        metadata.entry(key.clone()).or_insert_with(|| vec!(String::new(); count));
     }
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let values: Vec<&str> = line.split(',').map(|value| value.trim()).collect();
+        let execution_index = if by_id {
+            results.execution_ids.iter().position(|id| id == values[0])
+        } else {
+            values[0].parse::<usize>().ok().filter(|&index| index < count)
+        };
+        let execution_index = execution_index.ok_or(ParseError::InvalidFormat)?;
+        if values.len() != header.len() {
+            return Err(ParseError::InvalidFormat);
+        }
+        for (key, value) in header.iter().zip(values).skip(1) {
+            metadata.get_mut(key).unwrap()[execution_index] = value.to_owned();
+        }
+    }
+    Ok(results.without_executions(&[]).with_metadata(metadata))
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -103,7 +360,7 @@ mod tests {
     fn parse_string(input: &str) -> Result<Vec<Test>, ParseError> {
         let formatted = format!("{}\n", input);
         let reader = BufReader::new(formatted.as_bytes());
-        parse(reader)
+        parse_results(reader).map(|all| all.results)
     }
 
     fn result(test_name: &str, results: &[bool]) -> Result<Vec<Test>, ParseError> {
@@ -143,6 +400,92 @@ mod tests {
         )));
     }
 
+    #[test]
+    fn should_renumber_remaining_tests_when_excluding() {
+        let all = AllTestResults::new(parse_string("A,1\nB,0\nC,1").unwrap());
+        assert_eq!(all.without_tests(&[1]), AllTestResults::new(vec!(
+            test_history("A", 0, &[true]),
+            test_history("C", 1, &[true])
+        )));
+    }
+
+    #[test]
+    fn should_read_execution_ids() {
+        let formatted = "@id,run-1,run-2\nA,1,0\n";
+        let all = parse_results(BufReader::new(formatted.as_bytes())).unwrap();
+        assert_eq!(all.execution_ids, vec!("run-1".to_owned(), "run-2".to_owned()));
+        assert_eq!(all.without_executions(&[0]), AllTestResults::new(vec!(test_history("A", 0, &[false])))
+            .with_execution_ids(vec!("run-2".to_owned())));
+    }
+
+    #[test]
+    fn should_fail_invalid_format_if_execution_ids_dont_match_executions() {
+        let formatted = "@id,run-1\nA,1,0\n";
+        assert_eq!(parse_results(BufReader::new(formatted.as_bytes())), Err(ParseError::InvalidFormat));
+    }
+
+    #[test]
+    fn should_append_executions() {
+        let formatted = "@id,run-1,run-2\nA,1,0\nB,0,1\n";
+        let all = parse_results(BufReader::new(formatted.as_bytes())).unwrap();
+        let results = vec!(("B".to_owned(), true), ("C".to_owned(), true));
+        let appended = all.with_execution(&results, Some("run-3".to_owned()), &BTreeMap::new());
+        assert_eq!(appended.to_csv(), "@id,run-1,run-2,run-3\nA,1,0,0\nB,0,1,1\nC,0,0,1\n");
+        assert_eq!(parse_results(BufReader::new(appended.to_csv().as_bytes())), Ok(appended));
+    }
+
+    #[test]
+    fn should_read_execution_metadata() {
+        let formatted = "@id,run-1,run-2\n@commit,a1f,\n@timestamp,2026-09-01T10:00,2026-09-02T09:30\nA,1,0\n";
+        let all = parse_results(BufReader::new(formatted.as_bytes())).unwrap();
+        assert_eq!(all.metadata_value("commit", 0), Some("a1f"));
+        assert_eq!(all.metadata_value("commit", 1), None);
+        assert_eq!(all.execution_metadata(1).get("timestamp").map(String::as_str), Some("2026-09-02T09:30"));
+        assert_eq!(all.without_executions(&[0]).to_csv(), "@id,run-2\n@commit,\n@timestamp,2026-09-02T09:30\nA,0\n");
+
+        let mut metadata = BTreeMap::new();
+        metadata.insert("commit".to_owned(), "c07".to_owned());
+        metadata.insert("runner".to_owned(), "ci-4".to_owned());
+        let appended = all.with_execution(&[], Some("run-3".to_owned()), &metadata);
+        assert_eq!(appended.metadata["commit"], vec!("a1f".to_owned(), "".to_owned(), "c07".to_owned()));
+        assert_eq!(appended.metadata["runner"], vec!("".to_owned(), "".to_owned(), "ci-4".to_owned()));
+
+        let unlabelled = parse_results(BufReader::new("A,1,0\n".as_bytes())).unwrap();
+        assert_eq!(unlabelled.with_execution(&[], Some("run-3".to_owned()), &metadata).to_csv(),
+                   "@id,,,run-3\n@commit,,,c07\n@runner,,,ci-4\nA,1,0,0\n");
+        assert_eq!(parse_results(BufReader::new("@commit,a1f\nA,1,0\n".as_bytes())), Err(ParseError::InvalidFormat));
+    }
+
+    #[test]
+    fn should_read_skipped_tests() {
+        let formatted = "@skipped,,B;C\nA,1,1\nB,1,0\nC,0,0\n";
+        let all = parse_results(BufReader::new(formatted.as_bytes())).unwrap();
+        assert_eq!(all.skipped_tests(), vec!(vec!(), vec!(1, 2)));
+    }
+
+    #[test]
+    fn should_filter_executions_by_metadata() {
+        let formatted = "@id,run-1,run-2,run-3\nA,1,0,1\n";
+        let all = parse_results(BufReader::new(formatted.as_bytes())).unwrap();
+        let sidecar = "id,branch,timestamp\nrun-1,main,2026-08-31T23:00\nrun-3,main,2026-09-30T12:00\n";
+        let all = parse_metadata(BufReader::new(sidecar.as_bytes()), &all).unwrap();
+        assert_eq!(all.metadata_value("branch", 1), None);
+
+        let filter = ExecutionFilter {
+            labels: vec!(("branch".to_owned(), "main".to_owned())),
+            since: Some("2026-09-01".to_owned()),
+            until: None,
+        };
+        assert_eq!(all.matching(&filter).execution_ids, vec!("run-3".to_owned()));
+        let filter = ExecutionFilter { until: Some("2026-09-30".to_owned()), ..ExecutionFilter::default() };
+        assert_eq!(all.matching(&filter).execution_ids, vec!("run-1".to_owned(), "run-3".to_owned()));
+        let mut unusual = BTreeMap::new();
+        unusual.insert("timestamp".to_owned(), "2026-09-3é".to_owned());
+        assert!(!filter.matches(&unusual));
+        assert_eq!(parse_metadata(BufReader::new("id,branch\nrun-9,main\n".as_bytes()), &all),
+                   Err(ParseError::InvalidFormat));
+    }
+
     #[test]
     fn should_ignore_empty_line() {
         assert_eq!(parse_string("\nTest name,1"), result("Test name", &[true]));
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, BTreeSet};
+use expression::*;
+use reducer::sum_of_products;
+
+/// Rewrites the parts of an expression that need any `k` of the same `n` tests, like "any 2 of 3 replicas", as a
+/// single `AtLeast(k)` instead of every combination of them. Tests that every combination needs stay outside:
+/// `(A & B & C) | (A & B & D) | (A & C & D)` becomes `A & 2 of (B, C, D)`. Anything else is left as it is
+pub fn thresholds(expression: &Expression) -> Expression {
+    let all_terms = sum_of_products(expression);
+    let mut terms: Vec<BTreeSet<Literal>> = all_terms.iter()
+        .filter(|term| !all_terms.iter().any(|other| other != *term && other.is_subset(term))) // Absorbed
+        .cloned()
+        .collect();
+    terms.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b))); // Same order as the reducers give
+
+    let mut found = false;
+    let mut operands = Vec::<Operand>::new();
+    for component in components(&terms) {
+        match threshold_operand(&component) {
+            Some(operand) => {
+                found = true;
+                operands.push(operand);
+            },
+            None => operands.extend(component.iter().map(|term| and(term.iter().map(Literal::operand).collect()))),
+        }
+    }
+
+    if !found {
+        return expression.clone();
+    }
+    if operands.len() == 1 {
+        return match operands.remove(0) {
+            Operand::Expression(expression) => expression,
+            test => Expression { operator: Operator::Or, operands: vec!(test) }, // A lone test is still an operator
+        };
+    }
+    Expression { operator: Operator::Or, operands }
+}
+
+/// Splits the terms into groups that share no tests, in the order each group first appears
+fn components(terms: &[BTreeSet<Literal>]) -> Vec<Vec<BTreeSet<Literal>>> {
+    let tests: Vec<BTreeSet<i32>> = terms.iter()
+        .map(|term| term.iter().map(|literal| literal.test).collect())
+        .collect();
+
+    // Terms sharing a test take the lower of their labels, until every group is labelled by its first term
+    let mut labels: Vec<usize> = (0..terms.len()).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..terms.len() {
+            for j in 0..terms.len() {
+                if labels[j] < labels[i] && !tests[i].is_disjoint(&tests[j]) {
+                    labels[i] = labels[j];
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut components = BTreeMap::<usize, Vec<BTreeSet<Literal>>>::new();
+    for (&label, term) in labels.iter().zip(terms) {
+        components.entry(label).or_default().push(term.clone());
+    }
+    components.into_values().collect()
+}
+
+/// The terms as `common & k of (rest)`, if the rest are exactly every choice of `k` of the same literals
+fn threshold_operand(terms: &[BTreeSet<Literal>]) -> Option<Operand> {
+    if terms.len() < 3 {
+        return None; // 2 of 3 is the smallest threshold that isn't just an AND or an OR
+    }
+
+    let common: BTreeSet<Literal> = terms.iter().skip(1)
+        .fold(terms[0].clone(), |common, term| common.intersection(term).cloned().collect());
+    let rest: Vec<BTreeSet<Literal>> = terms.iter()
+        .map(|term| term.difference(&common).cloned().collect())
+        .collect();
+    let literals: BTreeSet<Literal> = rest.iter().flat_map(|term| term.iter().cloned()).collect();
+
+    // The terms are all different, so as many of them as there are choices means every choice is there
+    let threshold = rest[0].len();
+    if threshold < 2 || threshold >= literals.len()
+        || rest.iter().any(|term| term.len() != threshold)
+        || choose(literals.len(), threshold) != Some(rest.len()) {
+        return None;
+    }
+
+    let mut operands: Vec<Operand> = common.iter().map(Literal::operand).collect();
+    operands.push(Operand::Expression(Expression {
+        operator: Operator::AtLeast(threshold),
+        operands: literals.iter().map(Literal::operand).collect(),
+    }));
+    Some(and(operands))
+}
+
+/// How many ways there are to pick `k` of `n` things, or `None` if there are too many to count
+fn choose(n: usize, k: usize) -> Option<usize> {
+    (0..k).try_fold(1usize, |ways, i| ways.checked_mul(n - i).map(|ways| ways / (i + 1)))
+}
+
+/// An AND, unless there's only one operand
+fn and(mut operands: Vec<Operand>) -> Operand {
+    if operands.len() == 1 {
+        return operands.remove(0);
+    }
+    Operand::Expression(Expression { operator: Operator::And, operands })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn and(ids: &[i32]) -> Operand {
+        Operand::Expression(Expression {
+            operator: Operator::And,
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        })
+    }
+
+    fn or(operands: Vec<Operand>) -> Expression {
+        Expression { operator: Operator::Or, operands }
+    }
+
+    fn at_least(threshold: usize, ids: &[i32]) -> Expression {
+        Expression {
+            operator: Operator::AtLeast(threshold),
+            operands: ids.iter().map(|&id| Operand::Test(id)).collect(),
+        }
+    }
+
+    fn assert_equivalent(a: &Expression, b: &Expression) {
+        let variable_to_index: Vec<usize> = (0..8).collect();
+        for results in 0..(1u64 << 8) {
+            assert_eq!(a.evaluate_mask(results, &variable_to_index), b.evaluate_mask(results, &variable_to_index),
+                       "{:?} and {:?} differ at {:b}", a, b, results);
+        }
+    }
+
+    #[test]
+    fn should_spot_any_k_of_n() {
+        let two_of_three = or(vec!(and(&[0, 1]), and(&[0, 2]), and(&[1, 2])));
+        assert_eq!(thresholds(&two_of_three), at_least(2, &[0, 1, 2]));
+        assert_equivalent(&thresholds(&two_of_three), &two_of_three);
+
+        let three_of_four = or(vec!(and(&[0, 1, 2]), and(&[0, 1, 3]), and(&[0, 2, 3]), and(&[1, 2, 3])));
+        assert_eq!(thresholds(&three_of_four), at_least(3, &[0, 1, 2, 3]));
+        assert_equivalent(&thresholds(&three_of_four), &three_of_four);
+    }
+
+    #[test]
+    fn should_keep_shared_tests_and_other_terms_outside() {
+        // (E & F) | (A & 2 of (B, C, D))
+        let expression = or(vec!(and(&[0, 1, 2]), and(&[0, 1, 3]), and(&[4, 5]), and(&[0, 2, 3])));
+        let found = thresholds(&expression);
+        assert_eq!(found, or(vec!(
+            and(&[4, 5]),
+            Operand::Expression(Expression {
+                operator: Operator::And,
+                operands: vec!(Operand::Test(0), Operand::Expression(at_least(2, &[1, 2, 3]))),
+            }),
+        )));
+        assert_equivalent(&found, &expression);
+    }
+
+    #[test]
+    fn should_leave_everything_else_alone() {
+        let expressions = vec!(
+            or(vec!(and(&[0, 1]), and(&[2, 3]))),
+            or(vec!(and(&[0, 1]), and(&[0, 2]))), // Only two of the three pairs
+            or(vec!(and(&[0, 1]), and(&[1, 2]), and(&[2, 3]))),
+            or(vec!(Operand::Test(0), Operand::Test(1), Operand::Test(2))),
+        );
+        for expression in &expressions {
+            assert_eq!(&thresholds(expression), expression);
+        }
+    }
+}